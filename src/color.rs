@@ -0,0 +1,178 @@
+use miniz_oxide::deflate::compress_to_vec_zlib;
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+/// A Color is an RGBA value with channels from 0.0 to 1.0 - the same layout as macroquad's Color
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Color {
+    pub r: f32,
+    pub g: f32,
+    pub b: f32,
+    pub a: f32,
+}
+impl Color {
+    pub const fn new(r: f32, g: f32, b: f32, a: f32) -> Self {
+        Self { r, g, b, a }
+    }
+    /// to_bytes() converts the channels to 0..=255 the way macroquad does when filling an Image
+    pub fn to_bytes(self) -> [u8; 4] {
+        [
+            (self.r * 255.0) as u8,
+            (self.g * 255.0) as u8,
+            (self.b * 255.0) as u8,
+            (self.a * 255.0) as u8,
+        ]
+    }
+}
+impl fmt::Display for Color {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let [r, g, b, a] = self.to_bytes();
+        write!(f, "#{:02x}{:02x}{:02x}{:02x}", r, g, b, a)
+    }
+}
+impl FromStr for Color {
+    type Err = String;
+    /// from_str() reads "#rrggbb" or "#rrggbbaa" - colors without alpha are opaque
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex = s.strip_prefix('#').unwrap_or(s);
+        if !(hex.len() == 6 || hex.len() == 8) || !hex.is_ascii() {
+            return Err(format!("'{}' is not a color - use #rrggbb or #rrggbbaa", s));
+        }
+        let mut channels = [255_u8; 4];
+        for (channel, i) in channels.iter_mut().zip((0..hex.len()).step_by(2)) {
+            *channel = u8::from_str_radix(&hex[i..i + 2], 16)
+                .map_err(|_| format!("'{}' is not a color - use #rrggbb or #rrggbbaa", s))?;
+        }
+        // the middle of each byte's range so to_bytes() gives back the same byte
+        let [r, g, b, a] = channels.map(|channel| (channel as f32 + 0.5) / 255.0);
+        Ok(Color::new(r.min(1.0), g.min(1.0), b.min(1.0), a.min(1.0)))
+    }
+}
+pub const BLANK: Color = Color::new(0.00, 0.00, 0.00, 0.00);
+#[cfg(feature = "gui")]
+impl From<Color> for macroquad::color::Color {
+    fn from(color: Color) -> Self {
+        macroquad::color::Color::new(color.r, color.g, color.b, color.a)
+    }
+}
+#[cfg(feature = "gui")]
+impl From<macroquad::color::Color> for Color {
+    fn from(color: macroquad::color::Color) -> Self {
+        Color::new(color.r, color.g, color.b, color.a)
+    }
+}
+
+/// A Raster is an RGBA image held in memory - four bytes per pixel, row by row
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Raster {
+    pub width: usize,
+    pub height: usize,
+    pub bytes: Vec<u8>,
+}
+impl Raster {
+    /// new() creates a width by height raster filled with color
+    pub fn new(width: usize, height: usize, color: Color) -> Self {
+        Self {
+            width,
+            height,
+            bytes: color.to_bytes().repeat(width * height),
+        }
+    }
+    /// set_pixel() colors the pixel at (x, y)
+    pub fn set_pixel(&mut self, x: usize, y: usize, color: Color) {
+        let offset = (y * self.width + x) * 4;
+        self.bytes[offset..offset + 4].copy_from_slice(&color.to_bytes());
+    }
+    /// get_pixel() returns the RGBA bytes of the pixel at (x, y)
+    pub fn get_pixel(&self, x: usize, y: usize) -> [u8; 4] {
+        let offset = (y * self.width + x) * 4;
+        [
+            self.bytes[offset],
+            self.bytes[offset + 1],
+            self.bytes[offset + 2],
+            self.bytes[offset + 3],
+        ]
+    }
+    /// encode_png() returns the raster as the bytes of an 8-bit RGBA PNG file
+    pub fn encode_png(&self) -> Vec<u8> {
+        // every row starts with filter type 0 - no filtering
+        let mut scanlines: Vec<u8> = Vec::with_capacity((self.width * 4 + 1) * self.height);
+        for row in self.bytes.chunks(self.width.max(1) * 4).take(self.height) {
+            scanlines.push(0);
+            scanlines.extend_from_slice(row);
+        }
+        let mut header: Vec<u8> = Vec::with_capacity(13);
+        header.extend_from_slice(&(self.width as u32).to_be_bytes());
+        header.extend_from_slice(&(self.height as u32).to_be_bytes());
+        header.extend_from_slice(&[8, 6, 0, 0, 0]); // bit depth, RGBA, compression, filter, no interlace
+        let mut png: Vec<u8> = vec![0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+        png_chunk(&mut png, b"IHDR", &header);
+        png_chunk(&mut png, b"IDAT", &compress_to_vec_zlib(&scanlines, 6));
+        png_chunk(&mut png, b"IEND", &[]);
+        png
+    }
+    /// export_png() writes the raster to filename as a PNG image
+    pub fn export_png(&self, filename: &str) -> io::Result<()> {
+        File::create(filename)?.write_all(&self.encode_png())
+    }
+}
+// png_chunk() appends a length, type, data, and CRC-32 chunk to a PNG file
+fn png_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(kind.iter().chain(data.iter()));
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+// crc32() is the CRC used by PNG chunks (ISO 3309) - computed a byte at a time from a small table
+fn crc32<'a>(bytes: impl Iterator<Item = &'a u8>) -> u32 {
+    let mut table = [0_u32; 256];
+    for (n, entry) in table.iter_mut().enumerate() {
+        let mut c = n as u32;
+        for _ in 0..8 {
+            c = if c & 1 == 1 {
+                0xEDB8_8320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+        }
+        *entry = c;
+    }
+    !bytes.fold(0xFFFF_FFFF_u32, |crc, &byte| {
+        table[((crc ^ byte as u32) & 0xFF) as usize] ^ (crc >> 8)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn png_encoding() {
+        // the CRC of "IEND" with no data is fixed by the PNG specification
+        assert_eq!(crc32(b"IEND".iter()), 0xAE42_6082);
+        let mut raster = Raster::new(3, 2, BLANK);
+        raster.set_pixel(2, 1, Color::new(1.0, 0.0, 0.0, 1.0));
+        assert_eq!(raster.get_pixel(2, 1), [255, 0, 0, 255]);
+        // every byte survives a trip through hex
+        for byte in 0..=255_u8 {
+            let hex = format!("#{:02x}{:02x}{:02x}", byte, 255 - byte, byte / 2);
+            let color = hex.parse::<Color>().unwrap();
+            assert_eq!(color.to_bytes(), [byte, 255 - byte, byte / 2, 255]);
+            assert_eq!(color.to_string(), format!("{}ff", hex));
+        }
+        assert!("#12345".parse::<Color>().is_err());
+        assert!("#gg0000".parse::<Color>().is_err());
+        let png = raster.encode_png();
+        assert_eq!(&png[1..4], b"PNG");
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(&png[16..24], &[0, 0, 0, 3, 0, 0, 0, 2]);
+        assert_eq!(
+            &png[png.len() - 12..],
+            &[0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xAE, 0x42, 0x60, 0x82]
+        );
+    }
+}
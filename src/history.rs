@@ -0,0 +1,536 @@
+use crate::lak::decode_lkb;
+use crate::{Avalanche, Growth, Histogram, Journal, LakError, Model, Schedule};
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+
+pub const CHECKPOINT_EVERY: usize = 4_096; // grains between checkpoints - replaying this many takes about a second
+pub const CHECKPOINTS: usize = 256; // checkpoints kept before the oldest is forgotten
+const CELLS_PER_GRAIN: usize = 256; // bigger lattices take a checkpoint every cells / this many grains instead
+
+/// An Operation is a change to the drop cells or interval made while the simulation runs - replayed at the grain
+/// count it was made so rewinding through it rebuilds the same lattice
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operation {
+    Add { at: usize, cell: usize }, // a sandpile added at cell when the lattice held at grains
+    Remove { at: usize, slot: usize }, // drop cell slot stopped for good
+    Move { at: usize, slot: usize, cell: usize }, // drop cell slot moved to cell
+    Pause { at: usize, slot: usize }, // drop cell slot stopped until it is resumed
+    Resume { at: usize, slot: usize }, // drop cell slot dropping again
+    Rate { at: usize, slot: usize, rate: u32 }, // grains drop cell slot receives each turn from then on
+    Interval { at: usize, interval: usize },    // grains between screen updates from then on
+}
+impl Operation {
+    /// at() returns the total grains on the lattice when the operation was made
+    pub fn at(&self) -> usize {
+        match self {
+            Operation::Add { at, .. }
+            | Operation::Remove { at, .. }
+            | Operation::Move { at, .. }
+            | Operation::Pause { at, .. }
+            | Operation::Resume { at, .. }
+            | Operation::Rate { at, .. }
+            | Operation::Interval { at, .. } => *at,
+        }
+    }
+    // apply() makes the change to model
+    pub(crate) fn apply(&self, model: &mut Model) {
+        match *self {
+            Operation::Add { at, cell } => {
+                model.active_cells += 1;
+                model.drop_cells[model.active_cells - 1] = cell;
+                // remember when the pile was added so a saved scenario grows the same piles
+                model.schedules[model.active_cells - 1] = Schedule {
+                    start: at,
+                    ..Schedule::default()
+                };
+            }
+            Operation::Remove { slot, .. } => model.remove_drop_cell(slot),
+            Operation::Move { slot, cell, .. } => model.move_drop_cell(slot, cell),
+            // pausing leaves the start and stop alone so a resumed drop cell keeps to its schedule
+            Operation::Pause { at, slot } => model.schedules[slot].paused = Some(at),
+            Operation::Resume { slot, .. } => model.schedules[slot].paused = None,
+            Operation::Rate { slot, rate, .. } => model.schedules[slot].rate = rate,
+            Operation::Interval { interval, .. } => model.interval = interval,
+        }
+    }
+}
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operation::Add { at, cell } => write!(f, "add,{},{}", at, cell),
+            Operation::Remove { at, slot } => write!(f, "remove,{},{}", at, slot),
+            Operation::Move { at, slot, cell } => write!(f, "move,{},{},{}", at, slot, cell),
+            Operation::Pause { at, slot } => write!(f, "pause,{},{}", at, slot),
+            Operation::Resume { at, slot } => write!(f, "resume,{},{}", at, slot),
+            Operation::Rate { at, slot, rate } => write!(f, "rate,{},{},{}", at, slot, rate),
+            Operation::Interval { at, interval } => write!(f, "interval,{},{}", at, interval),
+        }
+    }
+}
+impl FromStr for Operation {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let pieces: Vec<&str> = s.split(',').collect();
+        let number = |i: usize| -> Result<usize, String> {
+            pieces
+                .get(i)
+                .and_then(|piece| piece.parse::<usize>().ok())
+                .ok_or(format!("'{}' is not an operation", s))
+        };
+        match (pieces[0], pieces.len()) {
+            ("add", 3) => Ok(Operation::Add {
+                at: number(1)?,
+                cell: number(2)?,
+            }),
+            ("remove", 3) => Ok(Operation::Remove {
+                at: number(1)?,
+                slot: number(2)?,
+            }),
+            ("move", 4) => Ok(Operation::Move {
+                at: number(1)?,
+                slot: number(2)?,
+                cell: number(3)?,
+            }),
+            ("pause", 3) => Ok(Operation::Pause {
+                at: number(1)?,
+                slot: number(2)?,
+            }),
+            ("resume", 3) => Ok(Operation::Resume {
+                at: number(1)?,
+                slot: number(2)?,
+            }),
+            ("rate", 4) => Ok(Operation::Rate {
+                at: number(1)?,
+                slot: number(2)?,
+                rate: u32::try_from(number(3)?)
+                    .map_err(|_| format!("'{}' is not an operation", s))?,
+            }),
+            ("interval", 3) => Ok(Operation::Interval {
+                at: number(1)?,
+                interval: number(2)?,
+            }),
+            _ => Err(format!("'{}' is not an operation", s)),
+        }
+    }
+}
+
+// A Checkpoint is a compressed copy of the model plus the state the .lkb format doesn't keep
+#[derive(Clone, Debug)]
+struct Checkpoint {
+    total_grains: usize,
+    lkb: Vec<u8>,
+    ac: usize,
+    turn: u32,
+    avalanche: Avalanche,
+    histogram: Histogram,
+    growth: Vec<Growth>,
+    saturated: bool,
+    applied: usize, // operations already made when the checkpoint was taken
+}
+
+/// A History keeps a ring of checkpoints and a log of operations so the simulation can be rewound to any grain
+/// count since the oldest checkpoint - the rules are deterministic given the drops and the seed, so replaying from
+/// the nearest earlier checkpoint rebuilds the lattice exactly
+#[derive(Clone, Debug)]
+pub struct History {
+    pub every: usize,    // grains between checkpoints
+    pub capacity: usize, // checkpoints kept
+    checkpoints: VecDeque<Checkpoint>,
+    operations: Vec<Operation>,
+    applied: usize, // operations made to the model so far - later ones wait to be replayed
+    latest: usize,  // most grains the timeline reaches
+    interval: usize, // interval the operations leave the model with
+    start: Option<String>, // header of the empty lattice the history began with - None if it began part way
+}
+impl History {
+    /// new() starts a history whose first checkpoint is model as it is now
+    pub fn new(model: &Model) -> Self {
+        let mut history = Self {
+            // compressing a checkpoint takes as long as the lattice is big, so big lattices take them further apart
+            every: CHECKPOINT_EVERY.max(model.cells.len() / CELLS_PER_GRAIN),
+            capacity: CHECKPOINTS,
+            checkpoints: VecDeque::new(),
+            operations: Vec::new(),
+            applied: 0,
+            latest: model.total_grains,
+            interval: model.interval,
+            // only a lattice no grain has touched can be rebuilt from a journal
+            start: Journal::new(model).map(|journal| journal.start),
+        };
+        history.checkpoint(model);
+        history
+    }
+    /// from_journal() rebuilds the empty lattice a journal starts from with a history that replays its operations
+    /// as the grains are dropped - the timeline reaches the end of the journal
+    pub fn from_journal(journal: &Journal) -> Result<(Self, Model), LakError> {
+        let model = journal.model()?;
+        let mut history = Self::new(&model);
+        history.operations = journal.operations.clone();
+        history.latest = journal.total_grains;
+        Ok((history, model))
+    }
+    /// journal() returns the start of the history and the operations made up to the model's grain count - None if
+    /// the history didn't begin with an empty lattice
+    pub fn journal(&self, model: &Model) -> Option<Journal> {
+        Some(Journal {
+            start: self.start.clone()?,
+            operations: self.operations[..self.applied].to_vec(),
+            total_grains: model.total_grains,
+        })
+    }
+    /// earliest() returns the grain count of the oldest checkpoint - the start of the timeline
+    pub fn earliest(&self) -> usize {
+        self.checkpoints
+            .front()
+            .map_or(self.latest, |checkpoint| checkpoint.total_grains)
+    }
+    /// latest() returns the most grains the model has held since the timeline was last cut short
+    pub fn latest(&self) -> usize {
+        self.latest
+    }
+    /// operations() returns every operation in the log, including any beyond the model's grain count
+    pub fn operations(&self) -> &[Operation] {
+        &self.operations
+    }
+    /// advance() drops up to grains more grains, making any logged operations as their grain counts are reached and
+    /// taking a checkpoint every so often - it stops early if the lattice saturates or every sandpile stops
+    pub fn advance(&mut self, model: &mut Model, grains: usize) {
+        let target = model.total_grains.saturating_add(grains);
+        self.replay(model, target);
+    }
+    /// add() adds a sandpile at cell - anything after the current grain count is forgotten first
+    pub fn add(&mut self, model: &mut Model, cell: usize) {
+        self.record(
+            model,
+            Operation::Add {
+                at: model.total_grains,
+                cell,
+            },
+        );
+    }
+    /// remove() stops drop cell slot for good - anything after the current grain count is forgotten first
+    pub fn remove(&mut self, model: &mut Model, slot: usize) {
+        if slot < model.active_cells {
+            self.record(
+                model,
+                Operation::Remove {
+                    at: model.total_grains,
+                    slot,
+                },
+            );
+        }
+    }
+    /// relocate() moves drop cell slot to cell - anything after the current grain count is forgotten first
+    pub fn relocate(&mut self, model: &mut Model, slot: usize, cell: usize) {
+        if slot < model.active_cells && cell < model.cells.len() && model.drop_cells[slot] != cell {
+            self.record(
+                model,
+                Operation::Move {
+                    at: model.total_grains,
+                    slot,
+                    cell,
+                },
+            );
+        }
+    }
+    /// pause() stops drop cell slot until it is resumed - anything after the current grain count is forgotten first
+    pub fn pause(&mut self, model: &mut Model, slot: usize) {
+        if slot < model.active_cells && model.schedules[slot].paused.is_none() {
+            self.record(
+                model,
+                Operation::Pause {
+                    at: model.total_grains,
+                    slot,
+                },
+            );
+        }
+    }
+    /// resume() lets paused drop cell slot drop again whenever its schedule says so - anything after the current
+    /// grain count is forgotten first
+    pub fn resume(&mut self, model: &mut Model, slot: usize) {
+        if slot < model.active_cells && model.schedules[slot].paused.is_some() {
+            self.record(
+                model,
+                Operation::Resume {
+                    at: model.total_grains,
+                    slot,
+                },
+            );
+        }
+    }
+    /// rate() sets the grains drop cell slot receives each turn - a pile with rate 3 gets three grains for each one
+    /// a pile with rate 1 gets - anything after the current grain count is forgotten first
+    pub fn rate(&mut self, model: &mut Model, slot: usize, rate: u32) {
+        if slot < model.active_cells && rate > 0 && model.schedules[slot].rate != rate {
+            self.record(
+                model,
+                Operation::Rate {
+                    at: model.total_grains,
+                    slot,
+                    rate,
+                },
+            );
+        }
+    }
+    /// note_interval() records a change to the model's interval so a replay updates the screen at the same grain
+    /// counts - anything after the current grain count is forgotten first
+    pub fn note_interval(&mut self, model: &mut Model) {
+        if model.interval != self.interval {
+            self.record(
+                model,
+                Operation::Interval {
+                    at: model.total_grains,
+                    interval: model.interval,
+                },
+            );
+        }
+    }
+    /// seek() rebuilds the model as it was at total grains - clamped to the timeline - and returns the grain count
+    /// reached, which is less than asked for if the sandpiles stopped first
+    pub fn seek(&mut self, model: &mut Model, total: usize) -> usize {
+        let total = total.clamp(self.earliest(), self.latest);
+        if let Some(checkpoint) = self
+            .checkpoints
+            .iter()
+            .rev()
+            .find(|checkpoint| checkpoint.total_grains <= total)
+            .cloned()
+        {
+            self.restore(model, &checkpoint);
+            self.replay(model, total);
+        }
+        model.total_grains
+    }
+    /// undo() takes back the most recent change to the sandpiles and rewinds to just before it - None if there is
+    /// nothing to undo or it happened before the oldest checkpoint
+    pub fn undo(&mut self, model: &mut Model) -> Option<Operation> {
+        let last = self.operations[..self.applied]
+            .iter()
+            .rposition(|operation| !matches!(operation, Operation::Interval { .. }))?;
+        let operation = self.operations[last];
+        if operation.at() < self.earliest() {
+            return None;
+        }
+        self.truncate(model.total_grains.min(operation.at()));
+        self.operations.truncate(last);
+        self.checkpoints
+            .retain(|checkpoint| checkpoint.applied <= last);
+        self.latest = operation.at();
+        self.seek(model, operation.at());
+        Some(operation)
+    }
+    // replay() drops grains until the model holds total, making the logged operations on the way
+    fn replay(&mut self, model: &mut Model, total: usize) {
+        loop {
+            while let Some(&operation) = self.operations.get(self.applied) {
+                if operation.at() > model.total_grains {
+                    break;
+                }
+                self.apply(model, operation);
+            }
+            if model.total_grains >= total || model.saturated || model.is_idle() {
+                break;
+            }
+            model.drop_grain();
+            self.latest = self.latest.max(model.total_grains);
+            // checkpoints already taken on an earlier pass through the timeline are kept
+            let newest = self.checkpoints.back().map_or(0, |last| last.total_grains);
+            if model.total_grains.is_multiple_of(self.every.max(1)) && model.total_grains > newest {
+                self.checkpoint(model);
+            }
+        }
+    }
+    // record() cuts the timeline short at the current grain count and makes operation
+    fn record(&mut self, model: &mut Model, operation: Operation) {
+        self.truncate(model.total_grains);
+        self.operations.push(operation);
+        self.apply(model, operation);
+    }
+    // apply() makes the next operation in the log
+    fn apply(&mut self, model: &mut Model, operation: Operation) {
+        operation.apply(model);
+        self.interval = model.interval;
+        self.applied += 1;
+    }
+    // truncate() forgets the checkpoints and operations beyond total grains - the timeline ends there
+    fn truncate(&mut self, total: usize) {
+        self.operations.truncate(self.applied);
+        self.checkpoints
+            .retain(|checkpoint| checkpoint.total_grains <= total);
+        self.latest = total;
+    }
+    // checkpoint() compresses the model onto the end of the ring, forgetting the oldest if the ring is full
+    fn checkpoint(&mut self, model: &Model) {
+        self.checkpoints.push_back(Checkpoint {
+            total_grains: model.total_grains,
+            lkb: model.encode_lkb(),
+            ac: model.ac,
+            turn: model.turn,
+            avalanche: model.avalanche,
+            histogram: model.histogram.clone(),
+            growth: model.growth.clone(),
+            saturated: model.saturated,
+            applied: self.applied,
+        });
+        while self.checkpoints.len() > self.capacity.max(1) {
+            self.checkpoints.pop_front();
+        }
+    }
+    // restore() replaces model with a checkpoint - the colors are a display choice and stay as they are
+    fn restore(&mut self, model: &mut Model, checkpoint: &Checkpoint) {
+        let mut restored =
+            decode_lkb(&checkpoint.lkb).expect("Checkpoints are written by encode_lkb()");
+        restored.ac = checkpoint.ac;
+        restored.turn = checkpoint.turn;
+        restored.avalanche = checkpoint.avalanche;
+        restored.histogram = checkpoint.histogram.clone();
+        restored.growth = checkpoint.growth.clone();
+        restored.saturated = checkpoint.saturated;
+        restored.hues = model.hues;
+        // sources aren't kept in checkpoints - tracking carries on from the restored lattice
+        restored.track_sources(!model.sources.is_empty());
+        *model = restored;
+        self.interval = model.interval;
+        self.applied = checkpoint.applied;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Rule;
+
+    fn grains(model: &Model) -> Vec<u8> {
+        model.cells.iter().map(|cell| cell.grains).collect()
+    }
+
+    #[test]
+    fn rewind_and_undo() {
+        let mut model = Model::new(40, 40);
+        model.rule = Rule::Manna;
+        model.active_cells = 1;
+        model.drop_cells[0] = model.xy_to_idx(15, 20);
+        let mut history = History::new(&model);
+        assert_eq!(history.every, CHECKPOINT_EVERY);
+        assert_eq!(History::new(&Model::new(2048, 2048)).every, 16_384);
+        history.every = 100;
+        history.advance(&mut model, 450);
+        let before_add = grains(&model);
+        let cell = model.xy_to_idx(25, 20);
+        history.add(&mut model, cell);
+        history.advance(&mut model, 300);
+        let middle = (
+            grains(&model),
+            model.histogram.clone(),
+            model.rng,
+            model.growth.clone(),
+        );
+        history.advance(&mut model, 250);
+        let end = grains(&model);
+        assert_eq!((history.earliest(), history.latest()), (0, 1000));
+        // rewinding through the added pile replays it, and moving forward again reaches the same end
+        assert_eq!(history.seek(&mut model, 750), 750);
+        assert_eq!(grains(&model), middle.0);
+        assert_eq!(model.histogram, middle.1);
+        assert_eq!(model.rng, middle.2);
+        assert_eq!(model.growth, middle.3);
+        assert_eq!(history.seek(&mut model, 300), 300);
+        assert_eq!(model.active_cells, 1);
+        history.advance(&mut model, 700);
+        assert_eq!(model.active_cells, 2);
+        assert_eq!(grains(&model), end);
+        // undo goes back to the moment before the pile was added and the timeline ends there
+        let undone = history.undo(&mut model).unwrap();
+        assert_eq!(undone.at(), 450);
+        assert_eq!(model.total_grains, 450);
+        assert_eq!(model.active_cells, 1);
+        assert_eq!(grains(&model), before_add);
+        assert_eq!(history.latest(), 450);
+        assert!(history.operations().is_empty());
+        assert_eq!(history.undo(&mut model), None);
+        // only the newest checkpoints are kept
+        history.capacity = 3;
+        history.advance(&mut model, 1000);
+        assert_eq!(history.earliest(), 1200);
+        assert_eq!(history.seek(&mut model, 0), 1200);
+    }
+
+    #[test]
+    fn manage_drop_cells() {
+        let mut model = Model::new(40, 40);
+        model.active_cells = 2;
+        model.drop_cells[0] = model.xy_to_idx(10, 20);
+        model.drop_cells[1] = model.xy_to_idx(30, 20);
+        model.schedules[1].stop = Some(5000);
+        let mut history = History::new(&model);
+        history.every = 256;
+        // a pile with rate 3 gets three grains for each one the other gets
+        history.rate(&mut model, 0, 3);
+        history.advance(&mut model, 400);
+        assert_eq!((model.growth[0].grains, model.growth[1].grains), (300, 100));
+        // a paused pile gets nothing until it is resumed
+        history.pause(&mut model, 1);
+        history.advance(&mut model, 200);
+        assert_eq!((model.growth[0].grains, model.growth[1].grains), (500, 100));
+        history.resume(&mut model, 1);
+        history.advance(&mut model, 400);
+        assert_eq!((model.growth[0].grains, model.growth[1].grains), (800, 200));
+        // resuming keeps the pile's own schedule
+        let scheduled = Schedule {
+            stop: Some(5000),
+            ..Schedule::default()
+        };
+        assert_eq!(model.schedules[1], scheduled);
+        // a moved pile grows again from its new cell
+        let cell = model.xy_to_idx(20, 5);
+        history.relocate(&mut model, 0, cell);
+        assert_eq!(model.drop_cells[0], cell);
+        assert_eq!(model.growth[0], Growth::default());
+        history.advance(&mut model, 100);
+        // the journal repeats every change
+        let journal = history.journal(&model).unwrap();
+        let filename = std::env::temp_dir()
+            .join(format!("lakhesis_piles_{}.journal", std::process::id()))
+            .display()
+            .to_string();
+        journal.save(&filename).unwrap();
+        let text = std::fs::read_to_string(&filename).unwrap();
+        let loaded = Journal::load(&filename).unwrap();
+        assert_eq!(loaded, journal);
+        assert!(text.ends_with("rate,0,0,3\npause,400,1\nresume,600,1\nmove,1000,0,220\n"));
+        let replayed = loaded.replay(usize::MAX).unwrap();
+        assert_eq!(grains(&replayed), grains(&model));
+        assert_eq!(replayed.schedules, model.schedules);
+        for (from, to) in [
+            ("rate,0,0,3", "rate,0,0,0"),
+            ("pause,400,1", "pause,400,2"),
+            ("move,1000,0,220", "move,1000,0,1600"),
+        ] {
+            std::fs::write(&filename, text.replacen(from, to, 1)).unwrap();
+            assert!(Journal::load(&filename).is_err());
+        }
+        std::fs::remove_file(&filename).unwrap();
+        // undo takes back the move
+        assert_eq!(
+            history.undo(&mut model),
+            Some(Operation::Move {
+                at: 1000,
+                slot: 0,
+                cell
+            })
+        );
+        assert_eq!(model.drop_cells[0], model.xy_to_idx(10, 20));
+        // and before the resume the pile is paused with its schedule untouched
+        history.seek(&mut model, 500);
+        assert!(!model.is_dropping(1));
+        assert_eq!(
+            model.schedules[1],
+            Schedule {
+                paused: Some(400),
+                ..scheduled
+            }
+        );
+    }
+}
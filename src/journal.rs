@@ -0,0 +1,224 @@
+use crate::lak::{field, malformed, parse_header, parse_metadata};
+use crate::{History, LakError, Model, Operation, MAX_DROPS};
+
+use std::fs;
+
+pub const JOURNAL_VERSION: u32 = 2; // version 2 added moving, pausing, resuming, and re-weighting drop cells
+
+/// A Journal is everything needed to repeat a simulation exactly - the empty lattice it started from and the changes
+/// made to its drop cells and interval as the grains fell - so a few hundred bytes can stand in for a whole lattice
+#[derive(Clone, Debug, PartialEq)]
+pub struct Journal {
+    pub start: String, // header, drops, hues, and schedule lines of the empty lattice - as a .lak file begins
+    pub operations: Vec<Operation>, // in the order they were made
+    pub total_grains: usize, // grains on the lattice when the journal ends
+}
+impl Journal {
+    /// new() starts a journal from model - None if grains have already touched the lattice
+    pub fn new(model: &Model) -> Option<Self> {
+        if model.total_grains > 0 || model.cells.iter().any(|cell| cell.borged) {
+            return None;
+        }
+        let mut start: Vec<u8> = Vec::new();
+        model
+            .write_metadata(&mut start)
+            .expect("Writing to memory can't fail");
+        Some(Self {
+            start: String::from_utf8_lossy(&start).to_string(),
+            operations: Vec::new(),
+            total_grains: 0,
+        })
+    }
+    /// save() writes the journal to filename - one line per operation after the starting lattice
+    pub fn save(&self, filename: &str) -> Result<(), LakError> {
+        let mut text = format!(
+            "journal,{},{}\n{}",
+            JOURNAL_VERSION, self.total_grains, self.start
+        );
+        for operation in self.operations.iter() {
+            text.push_str(&format!("{}\n", operation));
+        }
+        fs::write(filename, text)?;
+        Ok(())
+    }
+    /// load() reads a journal saved with save()
+    pub fn load(filename: &str) -> Result<Self, LakError> {
+        Self::parse(&fs::read_to_string(filename)?)
+    }
+    /// model() returns the empty lattice the journal starts from
+    pub fn model(&self) -> Result<Model, LakError> {
+        let mut lines = self
+            .start
+            .lines()
+            .enumerate()
+            .map(|(i, line)| (i + 2, line));
+        let mut model = match lines.next() {
+            Some((number, line)) => parse_header(number, line)?,
+            None => return Err(malformed(2, "the journal has no lattice")),
+        };
+        for (number, line) in lines {
+            if !parse_metadata(&mut model, number, &line.split(',').collect::<Vec<&str>>())? {
+                return Err(malformed(number, "unrecognized line"));
+            }
+        }
+        if model.total_grains > 0 {
+            return Err(malformed(2, "a journal starts from an empty lattice"));
+        }
+        Ok(model)
+    }
+    /// replay() rebuilds the model as it was at total grains - no further than the end of the journal
+    pub fn replay(&self, total: usize) -> Result<Model, LakError> {
+        let (mut history, mut model) = History::from_journal(self)?;
+        history.seek(&mut model, total);
+        Ok(model)
+    }
+    // parse() reads the text of a journal and checks that its operations fit the lattice
+    fn parse(text: &str) -> Result<Self, LakError> {
+        let mut lines = text.lines().enumerate().map(|(i, line)| (i + 1, line));
+        let (number, line) = lines
+            .next()
+            .ok_or_else(|| malformed(1, "the file is empty"))?;
+        let pieces: Vec<&str> = line.split(',').collect();
+        if pieces[0] != "journal" {
+            return Err(malformed(number, "not a lakhesis journal"));
+        }
+        if !pieces
+            .get(1)
+            .and_then(|version| version.parse::<u32>().ok())
+            .is_some_and(|version| (1..=JOURNAL_VERSION).contains(&version))
+        {
+            return Err(LakError::Version(pieces.get(1).unwrap_or(&"").to_string()));
+        }
+        let mut journal = Self {
+            start: String::new(),
+            operations: Vec::new(),
+            total_grains: field(&pieces, 2, number)?,
+        };
+        // the starting lattice runs until the first operation
+        let mut operations: Vec<(usize, &str)> = Vec::new();
+        for (number, line) in lines {
+            match line.parse::<Operation>() {
+                Ok(operation) => {
+                    journal.operations.push(operation);
+                    operations.push((number, line));
+                }
+                Err(_) if operations.is_empty() => {
+                    journal.start.push_str(line);
+                    journal.start.push('\n');
+                }
+                Err(why) => return Err(malformed(number, &why)),
+            }
+        }
+        let model = journal.model()?;
+        let mut active_cells = model.active_cells;
+        let mut at = 0;
+        for (&operation, &(number, _)) in journal.operations.iter().zip(operations.iter()) {
+            if operation.at() < at || operation.at() > journal.total_grains {
+                return Err(malformed(
+                    number,
+                    "operations must be in the order they were made",
+                ));
+            }
+            at = operation.at();
+            match operation {
+                Operation::Add { cell, .. } if cell >= model.cells.len() => {
+                    return Err(malformed(number, "a drop cell is beyond the lattice"));
+                }
+                Operation::Add { .. } if active_cells == MAX_DROPS => {
+                    return Err(malformed(number, "too many drop cells"));
+                }
+                Operation::Add { .. } => active_cells += 1,
+                Operation::Remove { slot, .. } if slot >= active_cells => {
+                    return Err(malformed(number, "there is no such drop cell"));
+                }
+                Operation::Remove { .. } => active_cells -= 1,
+                Operation::Move { cell, .. } if cell >= model.cells.len() => {
+                    return Err(malformed(number, "a drop cell is beyond the lattice"));
+                }
+                Operation::Rate { rate: 0, .. } => {
+                    return Err(malformed(number, "a drop cell needs a rate of at least 1"));
+                }
+                Operation::Move { slot, .. }
+                | Operation::Pause { slot, .. }
+                | Operation::Resume { slot, .. }
+                | Operation::Rate { slot, .. }
+                    if slot >= active_cells =>
+                {
+                    return Err(malformed(number, "there is no such drop cell"));
+                }
+                Operation::Move { .. }
+                | Operation::Pause { .. }
+                | Operation::Resume { .. }
+                | Operation::Rate { .. }
+                | Operation::Interval { .. } => (),
+            }
+        }
+        Ok(journal)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn journal_files() {
+        let mut model = Model::new(40, 40);
+        model.active_cells = 1;
+        model.drop_cells[0] = model.xy_to_idx(10, 20);
+        let mut history = History::new(&model);
+        history.every = 256;
+        history.advance(&mut model, 300);
+        let cell = model.xy_to_idx(30, 20);
+        history.add(&mut model, cell);
+        model.interval = 64;
+        history.note_interval(&mut model);
+        history.advance(&mut model, 400);
+        history.remove(&mut model, 0);
+        history.advance(&mut model, 300);
+        let journal = history.journal(&model).unwrap();
+        assert_eq!(journal.operations.len(), 3);
+        let filename = std::env::temp_dir()
+            .join(format!("lakhesis_{}.journal", std::process::id()))
+            .display()
+            .to_string();
+        journal.save(&filename).unwrap();
+        let text = fs::read_to_string(&filename).unwrap();
+        let loaded = Journal::load(&filename).unwrap();
+        fs::remove_file(&filename).unwrap();
+        assert_eq!(loaded, journal);
+        assert!(text.ends_with("add,300,830\ninterval,300,64\nremove,700,0\n"));
+        // replaying from the empty lattice reproduces every grain
+        let replayed = loaded.replay(usize::MAX).unwrap();
+        assert_eq!(replayed.total_grains, 1000);
+        assert_eq!((replayed.active_cells, replayed.interval), (1, 64));
+        assert_eq!(replayed.drop_cells[0], cell);
+        assert!((0..model.cells.len()).all(|i| replayed.cells[i].grains == model.cells[i].grains));
+        assert_eq!(replayed.histogram, model.histogram);
+        // journals that don't fit their lattice are reported
+        let broken =
+            |from: &str, to: &str| Journal::parse(&text.replacen(from, to, 1)).unwrap_err();
+        assert!(matches!(
+            broken("journal,2", "journal,7"),
+            LakError::Version(_)
+        ));
+        assert!(matches!(
+            broken("add,300,830", "add,300,9999"),
+            LakError::Malformed { .. }
+        ));
+        assert!(matches!(
+            broken("remove,700,0", "remove,700,5"),
+            LakError::Malformed { .. }
+        ));
+        assert!(matches!(
+            broken("remove,700", "remove,200"),
+            LakError::Malformed { .. }
+        ));
+        assert!(matches!(
+            broken("interval,300,64", "interval,300"),
+            LakError::Malformed { .. }
+        ));
+        // a history that began part way through can't be journaled
+        assert!(History::new(&model).journal(&model).is_none());
+    }
+}
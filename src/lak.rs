@@ -0,0 +1,877 @@
+use crate::model::{Cell, CRITICAL};
+use crate::{
+    Boundary, Color, Lattice, Model, Rule, Schedule, ALPHA, MAX_CRITICAL, MAX_DROPS, SEED,
+};
+
+use miniz_oxide::deflate::compress_to_vec_zlib;
+use miniz_oxide::inflate::decompress_to_vec_zlib;
+
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+pub const LAK_VERSION: u32 = 3; // version 1 files predate the version and say "alpha" - version 3 added paused
+const LKB_MAGIC: &[u8; 4] = b"LAKB"; // first bytes of a binary .lkb file
+const MAX_CELLS: usize = 1 << 28; // largest lattice a file may describe - 16_384 x 16_384 cells
+
+/// A LakError explains why a .lak or .lkb file could not be saved or loaded
+#[derive(Debug)]
+pub enum LakError {
+    Io(io::Error),
+    Version(String), // the header names a format this version of lakhesis can't read
+    Malformed {
+        line: usize,
+        reason: String,
+    },
+    Dimensions {
+        width: usize,
+        height: usize,
+        cells: usize,
+    }, // the cells don't fill the lattice
+    Checksum {
+        recorded: usize,
+        expected: usize,
+    },
+    Corrupt(String), // the cells of a binary file don't decode
+}
+impl fmt::Display for LakError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LakError::Io(why) => write!(f, "{}", why),
+            LakError::Version(version) => {
+                write!(f, "Unsupported .lak format version '{}'", version)
+            }
+            LakError::Malformed { line, reason } => write!(f, "Line {}: {}", line, reason),
+            LakError::Dimensions {
+                width,
+                height,
+                cells,
+            } => write!(
+                f,
+                "A {} x {} lattice has {} cells but the file holds {}",
+                width,
+                height,
+                width * height,
+                cells
+            ),
+            LakError::Checksum { recorded, expected } => write!(
+                f,
+                "Checksum error: {} of {} cells recorded",
+                recorded, expected
+            ),
+            LakError::Corrupt(reason) => write!(f, "Corrupt binary model: {}", reason),
+        }
+    }
+}
+impl std::error::Error for LakError {}
+impl From<io::Error> for LakError {
+    fn from(why: io::Error) -> Self {
+        LakError::Io(why)
+    }
+}
+
+/// A SavedModel describes a .lak, .lkb, or .journal file from its header alone - enough to choose one without
+/// loading it
+#[derive(Clone, Debug, PartialEq)]
+pub struct SavedModel {
+    pub path: PathBuf,
+    pub width: usize,
+    pub height: usize,
+    pub total_grains: usize,
+    pub active_cells: usize,
+    pub lattice: Lattice,
+    pub rule: Rule,
+}
+impl SavedModel {
+    /// read() reads the header of a saved model without decoding its cells
+    pub fn read(path: &Path) -> Result<Self, LakError> {
+        let mut file = BufReader::new(File::open(path)?);
+        let mut magic = [0_u8; 4];
+        file.read_exact(&mut magic)?;
+        // a binary header starts after the metadata length - a text header is the first line
+        let mut line: Vec<u8> = match &magic == LKB_MAGIC {
+            true => {
+                file.read_exact(&mut magic)?;
+                Vec::new()
+            }
+            false => magic.to_vec(),
+        };
+        file.read_until(b'\n', &mut line)?;
+        // a journal gives the grains it ends at before the header of its empty lattice
+        let mut total_grains: Option<usize> = None;
+        if line.starts_with(b"journal,") {
+            let journal = String::from_utf8_lossy(&line).trim_end().to_string();
+            total_grains = Some(field(&journal.split(',').collect::<Vec<&str>>(), 2, 1)?);
+            line.clear();
+            file.read_until(b'\n', &mut line)?;
+        }
+        let line = String::from_utf8_lossy(&line);
+        let pieces: Vec<&str> = line.trim_end().split(',').collect();
+        parse_version(&pieces, 1)?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            width: field(&pieces, 2, 1)?,
+            height: field(&pieces, 3, 1)?,
+            total_grains: match total_grains {
+                Some(total_grains) => total_grains,
+                None => field(&pieces, 4, 1)?,
+            },
+            active_cells: field(&pieces, 7, 1)?,
+            lattice: optional(&pieces, 12, 1)?.unwrap_or(Lattice::Square),
+            rule: optional(&pieces, 13, 1)?.unwrap_or(Rule::Btw),
+        })
+    }
+    /// find() lists the saved models and journals in folders by name - folders that can't be read and files that aren't models
+    /// are left out
+    pub fn find(folders: &[PathBuf]) -> Vec<Self> {
+        let mut saved: Vec<Self> = folders
+            .iter()
+            .filter_map(|folder| fs::read_dir(folder).ok())
+            .flatten()
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.extension().is_some_and(|extension| {
+                    ["lak", "lkb", "journal"].contains(&extension.to_string_lossy().as_ref())
+                })
+            })
+            .filter_map(|path| Self::read(&path).ok())
+            .collect();
+        saved.sort_by(|a, b| a.path.cmp(&b.path));
+        saved
+    }
+}
+
+impl Model {
+    /// curate() saves the model in its current state as a binary .lkb file and returns the name of the file
+    pub fn curate(&self) -> Result<String, LakError> {
+        let filename = format!("lakhesis_model_{:08}.lkb", &self.total_grains);
+        self.curate_as(&filename)?;
+        Ok(filename)
+    }
+    /// curate_as() saves the model in its current state to filename - binary if it ends in .lkb, otherwise text
+    pub fn curate_as(&self, filename: &str) -> Result<(), LakError> {
+        if filename.ends_with(".lkb") {
+            fs::write(filename, self.encode_lkb())?;
+            return Ok(());
+        }
+        let mut file = BufWriter::new(File::create(filename)?);
+        self.write_metadata(&mut file)?;
+        // cells are written in runs - untouched runs as a count, touched runs with one digit per cell
+        let mut written: usize = 0;
+        for run in self.cells.chunk_by(|a, b| a.borged == b.borged) {
+            written += run.len();
+            if run[0].borged {
+                write!(file, "{},t,", run.len())?;
+                for cell in run {
+                    write!(file, "{}", cell.grains)?;
+                }
+                writeln!(file)?;
+            } else {
+                writeln!(file, "{},f", run.len())?;
+            }
+        }
+        // continuous rules also need the energy of every touched cell
+        if self.rule.is_continuous() && self.energy.len() == self.cells.len() {
+            write!(file, "energy")?;
+            for i in 0..self.cells.len() {
+                if self.cells[i].borged {
+                    write!(file, ",{}", &self.energy[i])?;
+                }
+            }
+            writeln!(file)?;
+        }
+        write!(
+            file,
+            "Checksum: {} of {} cells recorded",
+            written,
+            self.cells.len()
+        )?;
+        file.flush()?;
+        Ok(())
+    }
+    /// uncurate() replaces the model with one saved using curate() in either format - the model is left alone if
+    /// the file can't be read
+    pub fn uncurate(&mut self, filename: &str) -> Result<(), LakError> {
+        let bytes = fs::read(filename)?;
+        *self = match bytes.starts_with(LKB_MAGIC) {
+            true => decode_lkb(&bytes)?,
+            false => parse_lak(
+                std::str::from_utf8(&bytes).map_err(|_| malformed(1, "not a lakhesis model"))?,
+            )?,
+        };
+        Ok(())
+    }
+    // write_metadata() writes the header, drops, hues, and schedule lines that both formats share
+    pub(crate) fn write_metadata(&self, file: &mut impl Write) -> io::Result<()> {
+        writeln!(
+            file,
+            "lakhesis,{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+            LAK_VERSION,
+            &self.width,
+            &self.height,
+            &self.total_grains,
+            &self.lost_grains,
+            &self.interval,
+            &self.active_cells,
+            &self.avalanche.topples,
+            &self.critical,
+            &self.boundary_x,
+            &self.boundary_y,
+            &self.lattice,
+            &self.rule,
+            &self.spread,
+            &self.dissipation,
+            &self.seed,
+            &self.rng.state,
+            &self.alpha,
+            &self.lost_energy
+        )?;
+        // all 32 drop cells whether active or not
+        write!(file, "drops")?;
+        for drop_cell in self.drop_cells.iter() {
+            write!(file, ",{}", drop_cell)?;
+        }
+        // hues are the untouched color followed by one color per grain count - four f32s each
+        write!(file, "\nhues")?;
+        for hue in [&self.hues.untouched]
+            .into_iter()
+            .chain(self.hues.grains.iter())
+        {
+            write!(file, ",{},{},{},{}", &hue.r, &hue.g, &hue.b, &hue.a)?;
+        }
+        writeln!(file)?;
+        // schedules are only written when a drop cell doesn't get one grain per turn from the start
+        let schedules = &self.schedules[..self.active_cells];
+        if schedules
+            .iter()
+            .any(|&schedule| schedule != Schedule::default())
+        {
+            write!(file, "schedule")?;
+            for schedule in schedules {
+                let stop = schedule.stop.map_or(String::new(), |stop| stop.to_string());
+                write!(file, ",{},{},{}", &schedule.rate, &schedule.start, &stop)?;
+            }
+            writeln!(file)?;
+        }
+        // the grain count each paused drop cell was paused at - empty for the others
+        if schedules.iter().any(|schedule| schedule.paused.is_some()) {
+            write!(file, "paused")?;
+            for schedule in schedules {
+                let paused = schedule.paused.map_or(String::new(), |at| at.to_string());
+                write!(file, ",{}", &paused)?;
+            }
+            writeln!(file)?;
+        }
+        Ok(())
+    }
+    /// encode_lkb() packs the model into the binary format - the text metadata, then the borged bitmap, the grains of
+    /// the borged cells at 2 or 3 bits each, and any energy, compressed together
+    pub fn encode_lkb(&self) -> Vec<u8> {
+        let mut metadata: Vec<u8> = Vec::new();
+        self.write_metadata(&mut metadata)
+            .expect("Writing to memory can't fail");
+        // as few bits as the fullest cell needs - never less than 2
+        let fullest = self.cells.iter().map(|cell| cell.grains).max().unwrap_or(0);
+        let bits = (u8::BITS - fullest.leading_zeros()).max(2) as u8;
+        let mut payload: Vec<u8> = vec![bits];
+        for byte in self.cells.chunks(8) {
+            payload.push(
+                byte.iter()
+                    .enumerate()
+                    .fold(0, |byte, (i, cell)| byte | ((cell.borged as u8) << i)),
+            );
+        }
+        let (mut buffer, mut filled) = (0_u32, 0_u32);
+        for cell in self.cells.iter().filter(|cell| cell.borged) {
+            buffer |= (cell.grains as u32) << filled;
+            filled += bits as u32;
+            while filled >= 8 {
+                payload.push(buffer as u8);
+                buffer >>= 8;
+                filled -= 8;
+            }
+        }
+        if filled > 0 {
+            payload.push(buffer as u8);
+        }
+        if self.rule.is_continuous() && self.energy.len() == self.cells.len() {
+            for i in 0..self.cells.len() {
+                if self.cells[i].borged {
+                    payload.extend_from_slice(&self.energy[i].to_le_bytes());
+                }
+            }
+        }
+        let mut lkb: Vec<u8> = LKB_MAGIC.to_vec();
+        lkb.extend_from_slice(&(metadata.len() as u32).to_le_bytes());
+        lkb.extend_from_slice(&metadata);
+        lkb.extend_from_slice(&compress_to_vec_zlib(&payload, 6));
+        lkb
+    }
+}
+
+// decode_lkb() rebuilds a model from the bytes of a binary .lkb file
+pub(crate) fn decode_lkb(bytes: &[u8]) -> Result<Model, LakError> {
+    let corrupt = |reason: &str| LakError::Corrupt(reason.to_string());
+    let length = bytes
+        .get(4..8)
+        .map(|length| u32::from_le_bytes([length[0], length[1], length[2], length[3]]) as usize)
+        .ok_or_else(|| corrupt("the file ends in the header"))?;
+    let metadata = bytes
+        .get(8..8 + length)
+        .ok_or_else(|| corrupt("the file ends in the header"))?;
+    let metadata = std::str::from_utf8(metadata).map_err(|_| corrupt("unreadable header"))?;
+    let mut lines = metadata.lines().enumerate().map(|(i, line)| (i + 1, line));
+    let mut model = match lines.next() {
+        Some((number, line)) => parse_header(number, line)?,
+        None => return Err(corrupt("the header is empty")),
+    };
+    for (number, line) in lines {
+        if !parse_metadata(&mut model, number, &line.split(',').collect::<Vec<&str>>())? {
+            return Err(malformed(number, "unrecognized line"));
+        }
+    }
+    // zlib's own checksum catches damage to the cells
+    let payload = decompress_to_vec_zlib(&bytes[8 + length..])
+        .map_err(|_| corrupt("the cells don't decompress"))?;
+    let size = model.width * model.height;
+    let bitmap = payload
+        .get(1..1 + size.div_ceil(8))
+        .ok_or_else(|| corrupt("the borged bitmap is short"))?;
+    let bits = payload[0] as u32;
+    if !(2..=8).contains(&bits) {
+        return Err(corrupt("impossible bits per cell"));
+    }
+    let mut cells: Vec<Cell> = (0..size)
+        .map(|i| Cell {
+            grains: 0,
+            borged: bitmap[i / 8] >> (i % 8) & 1 == 1,
+        })
+        .collect();
+    let borged = cells.iter().filter(|cell| cell.borged).count();
+    let packed = &payload[1 + bitmap.len()..];
+    let grain_bytes = (borged * bits as usize).div_ceil(8);
+    if packed.len() < grain_bytes {
+        return Err(corrupt("the grains are short"));
+    }
+    let mask = (1_u32 << bits) - 1;
+    let (mut buffer, mut filled, mut next) = (0_u32, 0_u32, 0);
+    for cell in cells.iter_mut().filter(|cell| cell.borged) {
+        if filled < bits {
+            buffer |= (packed[next] as u32) << filled;
+            filled += 8;
+            next += 1;
+        }
+        cell.grains = (buffer & mask) as u8;
+        buffer >>= bits;
+        filled -= bits;
+    }
+    let energy: Vec<f32> = packed[grain_bytes..]
+        .chunks_exact(4)
+        .map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .collect();
+    if !(energy.is_empty() || energy.len() == borged) || packed[grain_bytes..].len() % 4 != 0 {
+        return Err(corrupt("the energy doesn't match the borged cells"));
+    }
+    finish(model, cells, energy)
+}
+// parse_lak() rebuilds a model from the text of a .lak file
+fn parse_lak(text: &str) -> Result<Model, LakError> {
+    let mut lines = text.lines().enumerate().map(|(i, line)| (i + 1, line));
+    let mut model = match lines.next() {
+        Some((number, line)) => parse_header(number, line)?,
+        None => return Err(malformed(1, "the file is empty")),
+    };
+    let size = model.width * model.height;
+    let mut cells: Vec<Cell> = Vec::with_capacity(size);
+    let mut energy: Vec<f32> = Vec::new();
+    let mut checksum: Option<(usize, usize)> = None;
+    for (number, line) in lines {
+        let pieces: Vec<&str> = line.split(',').collect();
+        match pieces[0] {
+            _ if parse_metadata(&mut model, number, &pieces)? => (),
+            "energy" => {
+                energy = (1..pieces.len())
+                    .map(|i| field(&pieces, i, number))
+                    .collect::<Result<_, _>>()?;
+            }
+            _ if line.starts_with("Checksum:") => {
+                let words: Vec<&str> = line.split(' ').collect();
+                if words.len() < 4 {
+                    return Err(malformed(number, "incomplete checksum"));
+                }
+                checksum = Some((field(&words, 1, number)?, field(&words, 3, number)?));
+                break;
+            }
+            _ => {
+                let count: usize = field(&pieces, 0, number)?;
+                if cells.len() + count > size {
+                    return Err(LakError::Dimensions {
+                        width: model.width,
+                        height: model.height,
+                        cells: cells.len() + count,
+                    });
+                }
+                match (pieces.get(1), pieces.get(2)) {
+                    (Some(&"f"), None) => cells.resize(cells.len() + count, Cell::default()),
+                    (Some(&"t"), Some(grains)) if grains.len() == count => {
+                        for grain in grains.chars() {
+                            let grains = grain
+                                .to_digit(10)
+                                .ok_or_else(|| malformed(number, "grain counts must be digits"))?;
+                            cells.push(Cell {
+                                grains: grains as u8,
+                                borged: true,
+                            });
+                        }
+                    }
+                    _ => return Err(malformed(number, "unrecognized line")),
+                }
+            }
+        }
+    }
+    let (recorded, expected) = checksum
+        .ok_or_else(|| malformed(text.lines().count(), "the file ends before the checksum"))?;
+    if recorded != expected || expected != cells.len() {
+        return Err(LakError::Checksum { recorded, expected });
+    }
+    if cells.len() != size {
+        return Err(LakError::Dimensions {
+            width: model.width,
+            height: model.height,
+            cells: cells.len(),
+        });
+    }
+    finish(model, cells, energy)
+}
+// finish() checks the drop cells and gives the model its cells and energy - listed in the order of the touched cells
+fn finish(mut model: Model, cells: Vec<Cell>, energy: Vec<f32>) -> Result<Model, LakError> {
+    if model.drop_cells[..model.active_cells]
+        .iter()
+        .any(|&idx| idx >= cells.len())
+    {
+        return Err(malformed(2, "a drop cell is beyond the lattice"));
+    }
+    model.cells = cells;
+    if model.rule.is_continuous() {
+        model.energy = vec![0.0; model.cells.len()];
+        let touched = (0..model.cells.len()).filter(|&i| model.cells[i].borged);
+        for (i, energy) in touched.zip(energy) {
+            model.energy[i] = energy;
+        }
+    }
+    Ok(model)
+}
+// parse_metadata() reads a drops, hues, schedule, or paused line - false for any other line
+pub(crate) fn parse_metadata(
+    model: &mut Model,
+    number: usize,
+    pieces: &[&str],
+) -> Result<bool, LakError> {
+    match pieces[0] {
+        "drops" => {
+            if pieces.len() != MAX_DROPS + 1 {
+                return Err(malformed(number, "expected 32 drop cells"));
+            }
+            for i in 0..MAX_DROPS {
+                model.drop_cells[i] = field(pieces, i + 1, number)?;
+            }
+        }
+        "hues" => {
+            let channels: Vec<f32> = (1..pieces.len())
+                .map(|i| field(pieces, i, number))
+                .collect::<Result<_, _>>()?;
+            if channels.len() < 4 || !channels.len().is_multiple_of(4) {
+                return Err(malformed(number, "hues need four channels each"));
+            }
+            let mut hues = channels
+                .chunks(4)
+                .map(|rgba| Color::new(rgba[0], rgba[1], rgba[2], rgba[3]));
+            model.hues.untouched = hues.next().unwrap_or_default();
+            // older files only hold colors for zero to four grains
+            for (hue, color) in model.hues.grains.iter_mut().zip(hues) {
+                *hue = color;
+            }
+        }
+        "schedule" => {
+            if pieces.len() % 3 != 1 || pieces.len() / 3 > MAX_DROPS {
+                return Err(malformed(
+                    number,
+                    "schedules need a rate, start, and stop each",
+                ));
+            }
+            for (i, schedule) in model
+                .schedules
+                .iter_mut()
+                .take(pieces.len() / 3)
+                .enumerate()
+            {
+                *schedule = Schedule {
+                    rate: field(pieces, 3 * i + 1, number)?,
+                    start: field(pieces, 3 * i + 2, number)?,
+                    stop: match pieces[3 * i + 3] {
+                        "" => None,
+                        _ => Some(field(pieces, 3 * i + 3, number)?),
+                    },
+                    ..*schedule
+                };
+            }
+        }
+        "paused" => {
+            if pieces.len() > MAX_DROPS + 1 {
+                return Err(malformed(
+                    number,
+                    "more paused drop cells than there can be",
+                ));
+            }
+            for (i, schedule) in model
+                .schedules
+                .iter_mut()
+                .take(pieces.len() - 1)
+                .enumerate()
+            {
+                schedule.paused = match pieces[i + 1] {
+                    "" => None,
+                    _ => Some(field(pieces, i + 1, number)?),
+                };
+            }
+        }
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+// parse_header() reads the first line - fields added after the first version have defaults for older files
+pub(crate) fn parse_header(number: usize, line: &str) -> Result<Model, LakError> {
+    let pieces: Vec<&str> = line.split(',').collect();
+    parse_version(&pieces, number)?;
+    let width: usize = field(&pieces, 2, number)?;
+    let height: usize = field(&pieces, 3, number)?;
+    // a damaged header mustn't ask for more memory than any lattice needs
+    if width == 0
+        || height == 0
+        || width
+            .checked_mul(height)
+            .is_none_or(|size| size > MAX_CELLS)
+    {
+        return Err(malformed(number, "impossible lattice dimensions"));
+    }
+    let mut model = Model::new(width, height);
+    model.total_grains = field(&pieces, 4, number)?;
+    model.lost_grains = field(&pieces, 5, number)?;
+    model.interval = field(&pieces, 6, number)?;
+    model.active_cells = field(&pieces, 7, number)?;
+    if model.active_cells > MAX_DROPS {
+        return Err(malformed(number, "too many drop cells"));
+    }
+    model.avalanche.topples = field(&pieces, 8, number)?;
+    // files saved before the collapse threshold was configurable used four grains, open edges, square lattices,
+    // and the deterministic rule - random thresholds are drawn again from the seed
+    model.critical = optional(&pieces, 9, number)?.unwrap_or(CRITICAL);
+    model.boundary_x = optional(&pieces, 10, number)?.unwrap_or(Boundary::Open);
+    model.boundary_y = optional(&pieces, 11, number)?.unwrap_or(Boundary::Open);
+    model.lattice = optional(&pieces, 12, number)?.unwrap_or(Lattice::Square);
+    model.rule = optional(&pieces, 13, number)?.unwrap_or(Rule::Btw);
+    model.spread = optional(&pieces, 14, number)?.unwrap_or(0);
+    model.dissipation = optional(&pieces, 15, number)?.unwrap_or(0.0);
+    let seed = optional(&pieces, 16, number)?.unwrap_or(SEED);
+    let state = optional(&pieces, 17, number)?;
+    model.alpha = optional(&pieces, 18, number)?.unwrap_or(ALPHA);
+    model.lost_energy = optional(&pieces, 19, number)?.unwrap_or(0.0);
+    // settings no simulation could have been saved with would stall or break the first avalanche
+    if model.interval == 0 {
+        return Err(malformed(number, "the interval must be at least one grain"));
+    }
+    if model.critical < model.lattice.neighbors() || model.critical > MAX_CRITICAL {
+        return Err(malformed(
+            number,
+            &format!(
+                "a {} lattice collapses at {} to {} grains",
+                model.lattice,
+                model.lattice.neighbors(),
+                MAX_CRITICAL
+            ),
+        ));
+    }
+    if !(0.0..=1.0).contains(&model.dissipation) {
+        return Err(malformed(number, "the dissipation must be between 0 and 1"));
+    }
+    let max_alpha = model.rule.max_alpha(model.lattice);
+    if !(0.0..=max_alpha).contains(&model.alpha) {
+        return Err(malformed(
+            number,
+            &format!(
+                "alpha must be between 0 and {} for the {} rule on a {} lattice",
+                max_alpha, model.rule, model.lattice
+            ),
+        ));
+    }
+    // the thresholds are only drawn once the settings are known to be sound
+    model.reseed(seed);
+    if let Some(state) = state {
+        model.rng.state = state;
+    }
+    Ok(model)
+}
+// parse_version() checks that a header line belongs to a model this version of lakhesis can read
+fn parse_version(pieces: &[&str], number: usize) -> Result<(), LakError> {
+    if pieces[0] != "lakhesis" || pieces.len() < 9 {
+        return Err(malformed(number, "not a lakhesis model"));
+    }
+    // the original format has "alpha" where the version goes
+    let version = match pieces[1] {
+        "alpha" => Some(1),
+        version => version.parse::<u32>().ok(),
+    };
+    match version {
+        Some(version) if (1..=LAK_VERSION).contains(&version) => Ok(()),
+        _ => Err(LakError::Version(pieces[1].to_string())),
+    }
+}
+// field() parses piece i of line number
+pub(crate) fn field<T: FromStr>(pieces: &[&str], i: usize, number: usize) -> Result<T, LakError> {
+    match pieces.get(i) {
+        Some(piece) => piece
+            .parse::<T>()
+            .map_err(|_| malformed(number, &format!("'{}' is not a valid value", piece))),
+        None => Err(malformed(number, "the line is too short")),
+    }
+}
+// optional() parses piece i of line number if the line is long enough to have it
+fn optional<T: FromStr>(pieces: &[&str], i: usize, number: usize) -> Result<Option<T>, LakError> {
+    match i < pieces.len() {
+        true => field(pieces, i, number).map(Some),
+        false => Ok(None),
+    }
+}
+pub(crate) fn malformed(line: usize, reason: &str) -> LakError {
+    LakError::Malformed {
+        line,
+        reason: reason.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Journal;
+
+    fn temp(name: &str, extension: &str) -> String {
+        std::env::temp_dir()
+            .join(format!(
+                "lakhesis_{}_{}.{}",
+                name,
+                std::process::id(),
+                extension
+            ))
+            .display()
+            .to_string()
+    }
+
+    #[test]
+    fn lak_files() {
+        let mut model = Model::new(30, 20);
+        model.rule = Rule::Zhang;
+        model.active_cells = 2;
+        model.drop_cells[0] = model.xy_to_idx(10, 10);
+        model.drop_cells[1] = model.xy_to_idx(20, 10);
+        model.schedules[1].rate = 3;
+        model.hues.grains[2] = Color::new(0.25, 0.5, 0.75, 1.0);
+        for _ in 0..500 {
+            model.drop_grain();
+        }
+        let filename = temp("round_trip", "lak");
+        model.curate_as(&filename).unwrap();
+        let mut loaded = Model::default();
+        loaded.uncurate(&filename).unwrap();
+        let text = fs::read_to_string(&filename).unwrap();
+        fs::remove_file(&filename).unwrap();
+        assert!(text.starts_with(&format!("lakhesis,{},30,20,", LAK_VERSION)));
+        assert_eq!((loaded.width, loaded.height), (30, 20));
+        assert_eq!(loaded.total_grains, 500);
+        assert_eq!(loaded.rule, Rule::Zhang);
+        assert_eq!(loaded.energy, model.energy);
+        assert_eq!(loaded.schedules, model.schedules);
+        assert_eq!(loaded.hues.grains[2], model.hues.grains[2]);
+        assert_eq!(loaded.rng, model.rng);
+        assert!(
+            (0..model.cells.len()).all(|i| loaded.cells[i].grains == model.cells[i].grains
+                && loaded.cells[i].borged == model.cells[i].borged)
+        );
+        // the original format still loads with today's defaults
+        let legacy = "lakhesis,alpha,3,2,5,0,1024,1,0\ndrops,4,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0\n\
+                      hues,0,0,0,0,0,0.47,0.95,1,0,0.89,0.19,1,0.99,0.98,0,1,0,0,0,0,0.9,0.16,0.22,1\n\
+                      1,f\n3,t,121\n2,f\nChecksum: 6 of 6 cells recorded";
+        let legacy = parse_lak(legacy).unwrap();
+        assert_eq!(
+            (legacy.critical, legacy.lattice),
+            (CRITICAL, Lattice::Square)
+        );
+        assert_eq!(legacy.cells[2].grains, 2);
+        assert!(!legacy.cells[4].borged);
+        // damaged files are reported instead of panicking, and the model is left alone
+        let broken = |from: &str, to: &str| parse_lak(&text.replacen(from, to, 1)).unwrap_err();
+        assert!(
+            matches!(broken(&format!("lakhesis,{}", LAK_VERSION), "lakhesis,9"), LakError::Version(v) if v == "9")
+        );
+        assert!(matches!(
+            broken(",30,20,", ",31,20,"),
+            LakError::Dimensions { .. }
+        ));
+        assert!(matches!(
+            broken("Checksum: 600", "Checksum: 599"),
+            LakError::Checksum { .. }
+        ));
+        assert!(matches!(
+            broken("drops,", "drops,x"),
+            LakError::Malformed { line: 2, .. }
+        ));
+        assert!(matches!(broken("t,", "x,"), LakError::Malformed { .. }));
+        // headers that would hang or exhaust memory are refused before the lattice is made
+        let header: Vec<&str> = text.lines().next().unwrap().split(',').collect();
+        for changes in [
+            &[(2, "4294967296")][..],
+            &[(3, "2147483648")],
+            &[(6, "0")],
+            &[(9, "0")],
+            &[(9, "3")],
+            &[(9, "9")],
+            &[(15, "1.5")],
+            &[(15, "NaN")],
+            &[(18, "-0.1")],
+            // an OFC avalanche that hands out more energy than it holds never ends
+            &[(13, "ofc"), (18, "0.3")],
+            // random thresholds are drawn from the critical value up
+            &[(9, "9"), (14, "1")],
+        ] {
+            let mut pieces = header.clone();
+            for &(i, value) in changes {
+                pieces[i] = value;
+            }
+            assert!(
+                matches!(
+                    parse_header(1, &pieces.join(",")),
+                    Err(LakError::Malformed { .. })
+                ),
+                "{:?}",
+                changes
+            );
+        }
+        let truncated = parse_lak(text.rsplit_once('\n').unwrap().0);
+        assert!(matches!(truncated, Err(LakError::Malformed { .. })));
+        assert!(matches!(parse_lak(""), Err(LakError::Malformed { .. })));
+        let mut kept = Model::new(5, 5);
+        assert!(matches!(
+            kept.uncurate(&temp("missing", "lak")),
+            Err(LakError::Io(_))
+        ));
+        assert_eq!(kept.width, 5);
+    }
+
+    #[test]
+    fn lkb_files() {
+        let mut model = Model::new(40, 30);
+        model.rule = Rule::Zhang;
+        model.active_cells = 2;
+        model.drop_cells[0] = model.xy_to_idx(12, 15);
+        model.drop_cells[1] = model.xy_to_idx(28, 15);
+        model.schedules[1] = Schedule {
+            rate: 2,
+            start: 100,
+            stop: Some(900),
+            paused: Some(600),
+        };
+        for _ in 0..1000 {
+            model.drop_grain();
+        }
+        let (text, binary) = (temp("text", "lak"), temp("binary", "lkb"));
+        model.curate_as(&text).unwrap();
+        model.curate_as(&binary).unwrap();
+        let mut loaded = Model::default();
+        loaded.uncurate(&binary).unwrap();
+        let sizes = (
+            fs::metadata(&text).unwrap().len(),
+            fs::metadata(&binary).unwrap().len(),
+        );
+        // either format converts to the other without losing anything
+        let mut converted = Model::default();
+        converted.uncurate(&text).unwrap();
+        converted.curate_as(&binary).unwrap();
+        let bytes = fs::read(&binary).unwrap();
+        fs::remove_file(&text).unwrap();
+        fs::remove_file(&binary).unwrap();
+        assert!(sizes.1 < sizes.0);
+        assert_eq!(bytes, model.encode_lkb());
+        assert_eq!(&bytes[..4], LKB_MAGIC);
+        assert_eq!(loaded.total_grains, 1000);
+        assert_eq!(loaded.drop_cells, model.drop_cells);
+        assert_eq!(loaded.schedules, model.schedules);
+        assert_eq!(loaded.energy, model.energy);
+        assert_eq!(loaded.rng, model.rng);
+        assert!(
+            (0..model.cells.len()).all(|i| loaded.cells[i].grains == model.cells[i].grains
+                && loaded.cells[i].borged == model.cells[i].borged)
+        );
+        // cells holding more than three grains are packed at three bits
+        let mut full = Model::new(9, 1);
+        for (i, cell) in full.cells.iter_mut().enumerate() {
+            *cell = Cell {
+                grains: (i % 6) as u8,
+                borged: i > 0,
+            };
+        }
+        let decoded = decode_lkb(&full.encode_lkb()).unwrap();
+        assert!(
+            (0..9).all(|i| decoded.cells[i].grains == full.cells[i].grains
+                && decoded.cells[i].borged == full.cells[i].borged)
+        );
+        // damage anywhere is reported instead of panicking
+        let mut flipped = bytes.clone();
+        let last = flipped.len() - 6;
+        flipped[last] ^= 0xFF;
+        assert!(matches!(decode_lkb(&flipped), Err(LakError::Corrupt(_))));
+        for length in [3, 6, 40, bytes.len() - 1] {
+            assert!(decode_lkb(&bytes[..length]).is_err());
+        }
+        let mut version = bytes.clone();
+        version[8 + "lakhesis,".len()] = b'9';
+        assert!(matches!(decode_lkb(&version), Err(LakError::Version(_))));
+    }
+
+    #[test]
+    fn saved_models() {
+        let folder = std::env::temp_dir().join(format!("lakhesis_saved_{}", std::process::id()));
+        fs::create_dir_all(&folder).unwrap();
+        let mut model = Model::new(50, 40);
+        model.lattice = Lattice::Hexagonal;
+        model.critical = 6;
+        model.active_cells = 1;
+        model.drop_cells[0] = model.calc_center_idx();
+        for _ in 0..300 {
+            model.drop_grain();
+        }
+        let name = |file: &str| folder.join(file).display().to_string();
+        model.curate_as(&name("b.lkb")).unwrap();
+        model.drop_grain();
+        model.curate_as(&name("a.lak")).unwrap();
+        let mut journal = Journal::new(&Model::new(50, 40)).unwrap();
+        journal.total_grains = 5000;
+        journal.save(&name("c.journal")).unwrap();
+        // files that aren't models are left out of the list
+        fs::write(name("broken.lak"), "lakhesis,9,50,40").unwrap();
+        fs::write(name("notes.txt"), "lakhesis,2,50,40,0,0,1024,0,0").unwrap();
+        let saved = SavedModel::find(&[folder.clone(), folder.join("missing")]);
+        fs::remove_dir_all(&folder).unwrap();
+        assert_eq!(saved.len(), 3);
+        assert_eq!(saved[0].path, folder.join("a.lak"));
+        assert_eq!(saved[0].total_grains, 301);
+        assert_eq!(saved[1].total_grains, 300);
+        assert_eq!(saved[2].total_grains, 5000);
+        assert_eq!((saved[2].width, saved[2].height), (50, 40));
+        for saved in &saved[..2] {
+            assert_eq!((saved.width, saved.height), (50, 40));
+            assert_eq!((saved.lattice, saved.rule), (Lattice::Hexagonal, Rule::Btw));
+            assert_eq!(saved.active_cells, 1);
+        }
+    }
+}
@@ -14,10 +14,39 @@
  * limitations under the License.
  */
 
+mod color;
+mod history;
+mod journal;
+mod lak;
+#[cfg(feature = "gui")]
 mod lui;
 mod model;
+mod pattern;
+mod rng;
+mod runner;
+mod scenario;
+#[cfg(feature = "gui")]
 mod screen;
+mod seam;
+mod stats;
+mod sweep;
 
+pub use color::{Color, Raster};
+pub use history::{History, Operation, CHECKPOINTS, CHECKPOINT_EVERY};
+pub use journal::{Journal, JOURNAL_VERSION};
+pub use lak::{LakError, SavedModel, LAK_VERSION};
+#[cfg(feature = "gui")]
 pub use lui::{Control, Csliders, Info, RevertColor, Selected};
-pub use model::{Hues, Model, MAX_DROPS, MAX_ITERATIONS};
+pub use model::{
+    Boundary, Hues, Lattice, Model, Rule, Schedule, ALPHA, MAX_CRITICAL, MAX_DROPS, MAX_ITERATIONS,
+    MIN_CRITICAL, MODEL_HEIGHT, MODEL_WIDTH, SEED, UNFED,
+};
+pub use pattern::{Dimension, Patterns, Period, Symmetry, PERIOD_WINDOW};
+pub use rng::Rng;
+pub use runner::{command_line, Run};
+pub use scenario::{Pile, Scenario, Snapshots};
+#[cfg(feature = "gui")]
 pub use screen::Screen;
+pub use seam::{Seam, Seams};
+pub use stats::{Avalanche, Growth, Histogram, PowerLaw, FIT_MIN, GROWTH_MIN};
+pub use sweep::{Sweep, Trial, SWEEP_GRAINS};
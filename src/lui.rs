@@ -1,18 +1,33 @@
-use crate::{Hues, Model, Screen, MAX_DROPS};
+use crate::{
+    Boundary, Color, Growth, Histogram, History, Hues, Lattice, Model, Patterns, PowerLaw, Rule,
+    SavedModel, Scenario, Screen, Seams, Snapshots, ALPHA, FIT_MIN, MAX_CRITICAL, MAX_DROPS,
+    MAX_ITERATIONS, MODEL_HEIGHT, MODEL_WIDTH, SEED,
+};
 
-use macroquad::color::Color;
+use macroquad::color::colors::*;
 use macroquad::input::*;
 use macroquad::math::*;
+use macroquad::shapes::*;
+use macroquad::text::*;
 use macroquad::texture::*;
 use macroquad::time::*;
 use macroquad::ui::{hash, root_ui, widgets};
 
+use std::path::{Path, PathBuf};
+
 // maximum interval in sand grains added between screen updates - any higher and simulation becomes unresponsive
 const MAX_INTERVAL: usize = 16_384;
 // number of PNG frames to create 10 second video at 60fps
 const VIDEO_FRAME_COUNT: usize = 600;
+// range of lattice dimensions offered when starting a new simulation
+const MIN_LATTICE: f32 = 100.0;
+const MAX_LATTICE: f32 = 6_000.0;
 // set IO_SUPPORTED to false when compiling for WASM - currently saving an image to disk is not supported from web browser
 const IO_SUPPORTED: bool = false;
+// piles listed in the growth window
+const GROWTH_ROWS: usize = 6;
+// radius in pixels of the marker drawn on each drop cell while the drop cell manager is open
+const MARKER: f32 = 8.0;
 
 #[derive(Clone, Debug)]
 pub struct Info {
@@ -25,8 +40,8 @@ pub struct Info {
     pub ft_idx: usize,
     pub average_ft: f32,
 }
-impl Info {
-    pub fn default() -> Self {
+impl Default for Info {
+    fn default() -> Self {
         let running_ft = [0.0; 1024];
         Self {
             lattice_x: 0,
@@ -39,6 +54,8 @@ impl Info {
             average_ft: 0.0,
         }
     }
+}
+impl Info {
     /// get_average_ft() calculates the average frame time over the last 32 frames
     fn get_average_ft(&self) -> f32 {
         let mut average: f32 = 0.0;
@@ -48,7 +65,7 @@ impl Info {
         average / 1024.0
     }
     /// update() the info panel this frame
-    pub fn update(&mut self, screen: &Screen) {
+    pub fn update(&mut self, model: &Model, screen: &Screen) {
         self.current_ft = get_frame_time();
         if self.current_ft > self.longest_ft {
             self.longest_ft = self.current_ft;
@@ -60,20 +77,26 @@ impl Info {
             self.ft_idx = 0
         }
         self.average_ft = self.get_average_ft();
-        self.lattice_x = (screen.tlx + screen.mx).trunc() as usize;
-        self.lattice_y = (screen.tly + screen.my).trunc() as usize;
+        if let Some((x, y)) = screen.lattice_xy(model, screen.mx, screen.my) {
+            (self.lattice_x, self.lattice_y) = (x, y);
+        }
     }
 }
 
-#[derive(Clone, Copy, Debug, Default)]
+// names shown above the color buttons - untouched cells followed by each grain count
+const HUE_NAMES: [&str; MAX_CRITICAL as usize + 1] = [
+    "Null", "Zero", "One", "Two", "Three", "Four", "Five", "Six", "Seven",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Selected {
     Null,
-    Zero,
-    #[default]
-    One,
-    Two,
-    Three,
-    Four,
+    Grains(usize),
+}
+impl Default for Selected {
+    fn default() -> Self {
+        Selected::Grains(1)
+    }
 }
 
 #[derive(Clone, Copy, Debug, Default)]
@@ -84,36 +107,35 @@ pub struct Csliders {
     pub blue: f32,
     pub alpha: f32,
 }
+impl Csliders {
+    /// pick() selects a cell state and loads its current color into the sliders
+    pub fn pick(&mut self, selected: Selected, hues: &Hues) {
+        let color: Color = match selected {
+            Selected::Null => hues.untouched,
+            Selected::Grains(grains) => hues.grains[grains],
+        };
+        self.selected = selected;
+        self.red = color.r;
+        self.green = color.g;
+        self.blue = color.b;
+        self.alpha = color.a;
+    }
+}
 
 #[derive(Clone, Debug)]
 pub struct RevertColor {
     pub untouched: Color,
-    pub zero_grains: Color,
-    pub one_grain: Color,
-    pub two_grains: Color,
-    pub three_grains: Color,
-    pub four_grains: Color,
+    pub grains: [Color; MAX_CRITICAL as usize],
 }
 impl RevertColor {
     pub fn default(model: &Model) -> Self {
         let untouched: Color = model.hues.untouched;
-        let zero_grains: Color = model.hues.zero_grains;
-        let one_grain: Color = model.hues.one_grain;
-        let two_grains: Color = model.hues.two_grains;
-        let three_grains: Color = model.hues.three_grains;
-        let four_grains: Color = model.hues.four_grains;
-        Self {
-            untouched,
-            zero_grains,
-            one_grain,
-            two_grains,
-            three_grains,
-            four_grains,
-        }
+        let grains: [Color; MAX_CRITICAL as usize] = model.hues.grains;
+        Self { untouched, grains }
     }
 }
 
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug)]
 pub struct Control {
     pub visible: bool,
     pub magnify: bool,
@@ -123,9 +145,39 @@ pub struct Control {
     pub increment: bool,
     pub video: usize,
     pub color: bool,
+    pub limit: usize, // total sand grains before the simulation pauses - 0 for no limit
+    pub setup: bool,
+    pub lattice_width: f32, // dimensions of the lattice for the next new simulation
+    pub lattice_height: f32,
+    pub lattice_critical: f32, // collapse threshold for the next new simulation
+    pub lattice_boundary_x: Boundary, // edges for the next new simulation
+    pub lattice_boundary_y: Boundary,
+    pub lattice_shape: Lattice, // neighborhood for the next new simulation
+    pub lattice_rule: Rule,     // stochastic options for the next new simulation
+    pub lattice_spread: f32,
+    pub lattice_dissipation: f32,
+    pub lattice_seed: String,
+    pub lattice_alpha: f32, // OFC coupling for the next new simulation
+    pub distributions: bool,
+    pub scenario: Option<Scenario>, // scenario to start on the next reset
+    pub snapshots: Snapshots,       // grain counts at which the running simulation saves an image
+    pub picker: bool,               // the window listing saved models is open
+    pub saved: Vec<SavedModel>,     // saved models listed in that window
+    pub folders: Vec<PathBuf>, // folders searched for saved models - the working directory first
+    pub folder: String,        // folder typed into the window, not yet searched
+    pub load: Option<PathBuf>, // saved model to replace the running simulation
+    pub timeline: bool,        // the timeline window is open
+    pub timeline_grains: f32,  // grain count chosen on the timeline slider
+    pub scrubbing: bool, // the slider has moved - the model follows once the mouse is released
+    pub rewind: Option<usize>, // grain count to rebuild the model at
+    pub undo: bool,      // undo the most recent [A]dd
+    pub patterns: Option<Patterns>, // pattern measures shown in their window - None while it is closed
+    pub growth: bool,               // the window plotting the growth of each pile is open
+    pub piles: bool,                // the window managing the drop cells is open
+    pub dragging: Option<usize>,    // drop cell being dragged to a new cell
 }
-impl Control {
-    pub fn default() -> Self {
+impl Default for Control {
+    fn default() -> Self {
         Self {
             visible: true,
             magnify: false,
@@ -135,7 +187,119 @@ impl Control {
             increment: false,
             video: 0,
             color: false,
+            limit: MAX_ITERATIONS,
+            setup: false,
+            lattice_width: MODEL_WIDTH as f32,
+            lattice_height: MODEL_HEIGHT as f32,
+            lattice_critical: Lattice::Square.neighbors() as f32,
+            lattice_boundary_x: Boundary::Open,
+            lattice_boundary_y: Boundary::Open,
+            lattice_shape: Lattice::Square,
+            lattice_rule: Rule::Btw,
+            lattice_spread: 0.0,
+            lattice_dissipation: 0.0,
+            lattice_seed: SEED.to_string(),
+            lattice_alpha: ALPHA,
+            distributions: false,
+            scenario: None,
+            snapshots: Snapshots::default(),
+            picker: false,
+            saved: Vec::new(),
+            folders: vec![PathBuf::from(".")],
+            folder: String::new(),
+            load: None,
+            timeline: false,
+            timeline_grains: 0.0,
+            scrubbing: false,
+            rewind: None,
+            undo: false,
+            patterns: None,
+            growth: false,
+            piles: false,
+            dragging: None,
+        }
+    }
+}
+impl Control {
+    /// new_model() creates an empty model from a loaded scenario or the choices made in the new simulation window
+    pub fn new_model(&self) -> Model {
+        // scenarios are checked when they are loaded
+        if let Some(Ok(model)) = self.scenario.as_ref().map(Scenario::build) {
+            return model;
         }
+        let mut model = Model::new(self.lattice_width as usize, self.lattice_height as usize);
+        model.critical = self.lattice_critical as u8;
+        model.boundary_x = self.lattice_boundary_x;
+        model.boundary_y = self.lattice_boundary_y;
+        model.lattice = self.lattice_shape;
+        model.rule = self.lattice_rule;
+        model.spread = self.lattice_spread as u8;
+        model.dissipation = self.lattice_dissipation;
+        model.alpha = self.lattice_alpha;
+        model.reseed(self.lattice_seed.trim().parse::<u64>().unwrap_or(SEED));
+        model
+    }
+    /// restart() returns default controls that remember the choices made in the new simulation window
+    pub fn restart(&self) -> Self {
+        Self {
+            lattice_width: self.lattice_width,
+            lattice_height: self.lattice_height,
+            lattice_critical: self.lattice_critical,
+            lattice_boundary_x: self.lattice_boundary_x,
+            lattice_boundary_y: self.lattice_boundary_y,
+            lattice_shape: self.lattice_shape,
+            lattice_rule: self.lattice_rule,
+            lattice_spread: self.lattice_spread,
+            lattice_dissipation: self.lattice_dissipation,
+            lattice_seed: self.lattice_seed.clone(),
+            lattice_alpha: self.lattice_alpha,
+            folders: self.folders.clone(),
+            limit: self
+                .scenario
+                .as_ref()
+                .map_or(MAX_ITERATIONS, |scenario| scenario.grains),
+            snapshots: self
+                .scenario
+                .as_ref()
+                .map_or(Snapshots::default(), |scenario| scenario.snapshots.clone()),
+            ..Self::default()
+        }
+    }
+    /// can_open() is true for the paths that open() accepts - folders, saved models, journals, and scenarios
+    pub fn can_open(path: &Path) -> bool {
+        path.is_dir()
+            || path.is_file()
+                && path.extension().is_some_and(|extension| {
+                    ["lak", "lkb", "journal", "toml"]
+                        .contains(&extension.to_string_lossy().as_ref())
+                })
+    }
+    /// open() loads a saved model or journal or starts a scenario - a folder is added to the folders searched for saved models
+    pub fn open(&mut self, path: &Path, info: &mut Info) {
+        if path.is_dir() {
+            if !self.folders.iter().any(|folder| folder == path) {
+                self.folders.push(path.to_path_buf());
+            }
+        } else if path
+            .extension()
+            .is_some_and(|extension| extension == "toml")
+        {
+            match Scenario::load(&path.display().to_string()) {
+                Ok(scenario) => {
+                    self.scenario = Some(scenario);
+                    self.reset = true;
+                }
+                Err(why) => info.context = why,
+            }
+        } else {
+            self.load = Some(path.to_path_buf());
+        }
+    }
+    /// show_saved() opens the window listing the saved models in every folder
+    pub fn show_saved(&mut self) {
+        self.saved = SavedModel::find(&self.folders);
+        self.picker = true;
+        self.paused = true;
     }
     /// draw_panel(), if visible, to provide control options
     pub fn draw_panel(&mut self, model: &mut Model, info: &mut Info, screen: &mut Screen) {
@@ -179,7 +343,8 @@ impl Control {
                         }
                     }
                     if widgets::Button::new("RESET").size(vec2(75., 26.)).ui(ui) {
-                        self.reset = true;
+                        self.setup = true;
+                        self.paused = true;
                     }
                 });
             widgets::Group::new(hash!(), Vec2::new(153., 200.))
@@ -189,7 +354,11 @@ impl Control {
                     ui.label(Vec2::new(7., 15.), &format!("Sandpiles:       {:2}", &model.active_cells));
                     ui.label(Vec2::new(7., 35.), "Sand Grains");
                     ui.label(Vec2::new(7., 50.), &format!("Total:   {:10}", &model.total_grains));
-                    ui.label(Vec2::new(7., 65.), &format!("Lost:    {:10}", &model.lost_grains));
+                    if model.rule.is_continuous() {
+                        ui.label(Vec2::new(7., 65.), &format!("Lost:    {:10.1}", &model.lost_energy));
+                    } else {
+                        ui.label(Vec2::new(7., 65.), &format!("Lost:    {:10}", &model.lost_grains));
+                    }
                     ui.label(Vec2::new(7., 85.), "Frame Times");
                     ui.label(Vec2::new(7., 100.), &format!("FPS:       {:2}", &get_fps()));
                     ui.label(Vec2::new(7., 115.), &format!("Current:   {:8.5}", &info.current_ft));
@@ -218,50 +387,55 @@ impl Control {
             widgets::Group::new(hash!(), Vec2::new(153. ,70.))
                 .position(Vec2::new(82., 202.))
                 .ui(ui, |ui| {
-                    ui.label(None, "     ");
+                    if widgets::Button::new("+").size(vec2(20., 20.)).ui(ui) {
+                        // zoom in around the center of the screen
+                        screen.zoom_at(model, screen.zoom * 2.0, screen.width / 2.0, screen.height / 2.0);
+                    }
                     ui.same_line(0.);
-                    if widgets::Button::new("UP").size(vec2(70., 20.)).ui(ui) {
+                    if widgets::Button::new("UP").size(vec2(100., 20.)).ui(ui) {
                         // move screen up 256 pixels
-                        screen.tly -= 256.0;
-                        if screen.tly < 0.0 {
-                            screen.tly = 0.0;
-                        };
+                        screen.pan(model, 0.0, -256.0);
+                    }
+                    ui.same_line(0.);
+                    if widgets::Button::new("-").size(vec2(20., 20.)).ui(ui) {
+                        // zoom out around the center of the screen
+                        screen.zoom_at(model, screen.zoom / 2.0, screen.width / 2.0, screen.height / 2.0);
                     }
                     if widgets::Button::new("LEFT").size(vec2(60., 20.)).ui(ui) {
                         // move screen left 256 pixels
-                        screen.tlx -= 256.0;
-                        if screen.tlx < 0.0 {
-                            screen.tlx = 0.0;
-                        };
+                        screen.pan(model, -256.0, 0.0);
                     }
                     ui.same_line(0.);
                     if widgets::Button::new("O").size(vec2(20., 20.)).ui(ui) {
                         // center screen
-                        screen.tlx = (model.width as f32 - screen.width).abs() / 2.0;
-                        screen.tly = (model.height as f32 - screen.height).abs() / 2.0;
+                        screen.center(model);
                     }
                     ui.same_line(0.);
                     if widgets::Button::new("RIGHT").size(vec2(60., 20.)).ui(ui) {
                         // move screen right 256 pixels
-                        screen.tlx += 256.0;
-                        if (screen.tlx + screen.width) >= model.width as f32 {
-                            screen.tlx = model.width as f32 - screen.width;
-                        };
+                        screen.pan(model, 256.0, 0.0);
+                    }
+                    if widgets::Button::new("F").size(vec2(20., 20.)).ui(ui) {
+                        // zoom out to fit the whole lattice on screen
+                        let zoom = screen.fit_zoom(model);
+                        screen.zoom_at(model, zoom, 0.0, 0.0);
+                        screen.center(model);
                     }
-                    ui.label(None, "     ");
                     ui.same_line(0.);
-                    if widgets::Button::new("DOWN").size(vec2(70., 20.)).ui(ui) {
+                    if widgets::Button::new("DOWN").size(vec2(100., 20.)).ui(ui) {
                         // move screen down 256 pixels
-                        screen.tly += 256.0;
-                        if (screen.tly + screen.height) >= model.height as f32 {
-                            screen.tly = model.height as f32 - screen.height;
-                        };
+                        screen.pan(model, 0.0, 256.0);
+                    }
+                    ui.same_line(0.);
+                    if widgets::Button::new("1").size(vec2(20., 20.)).ui(ui) {
+                        // one pixel per cell
+                        screen.zoom_at(model, 1.0, screen.width / 2.0, screen.height / 2.0);
                     }
                 });
         });
     }
     /// check_keyboard() handles keyboard commands
-    pub fn check_keyboard(&mut self, model: &mut Model, info: &mut Info) {
+    pub fn check_keyboard(&mut self, model: &mut Model, history: &History, info: &mut Info) {
         match get_last_key_pressed() {
             Some(KeyCode::A) => {
                 // add a new active cell
@@ -279,22 +453,58 @@ impl Control {
                 self.color = true;
                 self.paused = true;
             }
+            Some(KeyCode::D) => {
+                // show or hide the avalanche distributions
+                self.distributions = !self.distributions;
+            }
+            Some(KeyCode::E) => {
+                // measure the patterns of the lattice or close their window
+                self.patterns = match self.patterns {
+                    Some(_) => None,
+                    None => Some(Patterns::measure(model)),
+                };
+            }
+            Some(KeyCode::W) => {
+                // show or hide the growth of each pile
+                self.growth = !self.growth;
+            }
             Some(KeyCode::G) => {
                 if IO_SUPPORTED {
-                    model.curate();
+                    info.context = match model.curate() {
+                        // a journal that repeats the simulation is saved alongside when there is one
+                        Ok(filename) => match history
+                            .journal(model)
+                            .map(|journal| journal.save(&filename.replace(".lkb", ".journal")))
+                        {
+                            Some(Err(why)) => {
+                                format!(
+                                    "Model saved as '{}' but not its journal - {}",
+                                    filename, why
+                                )
+                            }
+                            Some(Ok(())) => format!("Model and journal saved as '{}'", filename),
+                            None => format!("Model saved as '{}'", filename),
+                        },
+                        Err(why) => format!("The model could not be saved - {}", why),
+                    };
                 } else {
                     info.context =
                         "Exporting data to file not supported in web browsers".to_string();
                 }
             }
             Some(KeyCode::H) => {
-                // new simulation - reset to default
+                // replace the simulation with a saved one
                 if IO_SUPPORTED {
                     if is_key_down(KeyCode::LeftControl) || is_key_down(KeyCode::RightControl) {
-                        model.uncurate("lakhesis.lak".to_string());
+                        // the binary format is preferred when both files are present
+                        self.load = match Path::new("lakhesis.lkb").exists() {
+                            true => Some(PathBuf::from("lakhesis.lkb")),
+                            false => Some(PathBuf::from("lakhesis.lak")),
+                        };
                     } else {
+                        self.show_saved();
                         info.context =
-                            "Press [CTRL][H] to load a saved simulation named 'lakhesis.lak' or [ESC] to cancel"
+                            "Click a saved simulation to load it - [CTRL][H] loads 'lakhesis.lkb' or 'lakhesis.lak' directly"
                                 .to_string();
                     }
                 } else {
@@ -306,12 +516,35 @@ impl Control {
                 self.visible = !self.visible;
                 info.context = "<--Click here to hide the control panel".to_string();
             }
+            Some(KeyCode::K) => {
+                // show or hide the drop cell manager
+                self.piles = !self.piles;
+            }
+            Some(KeyCode::L) => {
+                // double the grain limit or, with [CTRL], remove it
+                if is_key_down(KeyCode::LeftControl) || is_key_down(KeyCode::RightControl) {
+                    self.limit = 0;
+                    info.context =
+                        "Grain limit removed - the simulation will run until paused".to_string();
+                } else {
+                    if self.limit == 0 {
+                        self.limit = MAX_ITERATIONS;
+                    } else {
+                        self.limit = self.limit.saturating_mul(2);
+                    }
+                    info.context = format!(
+                        "Simulation will pause at {} sand grains - press [CTRL][L] to remove the limit",
+                        &self.limit
+                    );
+                }
+            }
             Some(KeyCode::M) => self.magnify = !self.magnify,
             Some(KeyCode::N) => {
                 // new simulation - reset to default
                 if IO_SUPPORTED {
                     if is_key_down(KeyCode::LeftControl) || is_key_down(KeyCode::RightControl) {
-                        self.reset = true;
+                        self.setup = true;
+                        self.paused = true;
                     } else {
                         info.context =
                             "Press [CTRL][N] to start a new simulation or [ESC] to cancel"
@@ -323,17 +556,85 @@ impl Control {
                             .to_string();
                 }
             }
+            Some(KeyCode::O) => {
+                if is_key_down(KeyCode::LeftControl) || is_key_down(KeyCode::RightControl) {
+                    // export the seams between the territories of the piles
+                    info.context = match (IO_SUPPORTED, model.sources.is_empty()) {
+                        (false, _) => {
+                            "Exporting data to file not supported in web browsers".to_string()
+                        }
+                        (true, true) => {
+                            "Press [O] to find the territories of the sandpiles first".to_string()
+                        }
+                        (true, false) => {
+                            let seams = Seams::find(model);
+                            let name = format!("lakhesis_seams_{:08}", model.total_grains);
+                            match seams.save(&name) {
+                                Ok(()) => format!(
+                                    "{} seams exported as '{}.csv', '{}_lines.csv', and '{}.png'",
+                                    seams.seams.len(),
+                                    name,
+                                    name,
+                                    name
+                                ),
+                                Err(why) => format!("The seams could not be exported - {}", why),
+                            }
+                        }
+                    };
+                } else {
+                    // tint each cell by the sandpile that fed it most
+                    model.track_sources(model.sources.is_empty());
+                    info.context = match model.sources.is_empty() {
+                        true => "Cells are colored by their grains again".to_string(),
+                        false => {
+                            "Cells are tinted by the sandpile that fed them most - press [O] to stop or [CTRL][O] to export the seams"
+                                .to_string()
+                        }
+                    };
+                }
+            }
             Some(KeyCode::P) => self.paused = !self.paused, // pause or restart the simulation
             Some(KeyCode::S) => {
                 if IO_SUPPORTED {
                     let (min_x, min_y, extant_width, extant_height) = model.find_extent();
                     model.paint(min_x, min_y, extant_width, extant_height);
                     info.context = format!("Lakhesis_{:08}.png exported", &model.total_grains);
+                    // the patterns are saved alongside while their window is open
+                    if self.patterns.is_some() {
+                        let filename = format!("Lakhesis_{:08}_patterns.csv", &model.total_grains);
+                        info.context = self.export_patterns(model, &filename);
+                    }
                 } else {
                     info.context =
                         "Exporting images to file not supported in web browsers".to_string();
                 }
             }
+            Some(KeyCode::T) => {
+                // save the setup as a scenario or, with [CTRL], start the scenario saved as 'lakhesis.toml'
+                if IO_SUPPORTED {
+                    if is_key_down(KeyCode::LeftControl) || is_key_down(KeyCode::RightControl) {
+                        match Scenario::load("lakhesis.toml") {
+                            Ok(scenario) => {
+                                self.scenario = Some(scenario);
+                                self.reset = true;
+                            }
+                            Err(why) => info.context = why,
+                        }
+                    } else {
+                        let scenario = Scenario {
+                            snapshots: self.snapshots.clone(),
+                            ..Scenario::from_model(model, self.limit)
+                        };
+                        info.context = match scenario.save("lakhesis_scenario.toml") {
+                            Ok(()) => "Scenario saved as 'lakhesis_scenario.toml' - press [CTRL][T] to start a scenario named 'lakhesis.toml'".to_string(),
+                            Err(why) => why,
+                        };
+                    }
+                } else {
+                    info.context =
+                        "Saving and loading scenarios is not supported in web browsers".to_string();
+                }
+            }
             Some(KeyCode::V) => {
                 // collect frames at set interval for use as a GIF or video - the PNGs will encompass the visible portion of the model
                 if IO_SUPPORTED {
@@ -349,23 +650,39 @@ impl Control {
                         "Exporting images to file not supported in web browsers".to_string();
                 }
             }
+            Some(KeyCode::R) => {
+                // show or hide the timeline
+                self.timeline = !self.timeline;
+                self.paused = true;
+            }
+            Some(KeyCode::Z) => {
+                if is_key_down(KeyCode::LeftControl) || is_key_down(KeyCode::RightControl) {
+                    self.undo = true;
+                    self.paused = true;
+                } else {
+                    info.context =
+                        "Press [CTRL][Z] to take back the last sandpile change and rewind to just before it"
+                            .to_string();
+                }
+            }
+            Some(KeyCode::Left) if self.video == 0 => {
+                // step back one interval
+                self.rewind = Some(model.total_grains.saturating_sub(model.interval));
+                self.paused = true;
+            }
             Some(KeyCode::Space) => {
                 // spacebar to step one interval at a time
                 self.paused = true; // spacebar is frame-step, so ensure we're paused
                 self.increment = true;
                 info.context = "Press [Spacebar] again or click 'STEP' to increment model one interval - 'PAUSE' or [P] key to resume automatic updates".to_string();
             }
-            Some(KeyCode::Up) => {
-                // increase interval by 4x up to 65_536 (4^8)
-                if model.interval < MAX_INTERVAL && self.video == 0 {
-                    model.interval *= 4;
-                };
+            Some(KeyCode::Up) if model.interval < MAX_INTERVAL && self.video == 0 => {
+                // increase interval by 4x up to 16_384 (4^7)
+                model.interval *= 4;
             }
-            Some(KeyCode::Down) => {
+            Some(KeyCode::Down) if model.interval > 1 && self.video == 0 => {
                 // decrease interval by 4x down to 1
-                if model.interval > 1 && self.video == 0 {
-                    model.interval /= 4;
-                };
+                model.interval /= 4;
             }
             Some(KeyCode::Escape) => {
                 if self.add {
@@ -375,106 +692,764 @@ impl Control {
                 if self.video > 0 {
                     self.video = 0;
                 };
+                self.picker = false;
+                self.timeline = false;
                 info.context = "<--Click here to hide the control panel".to_string();
             }
             None => (),
             _ => (),
         }
     }
-    /// change_color() allows user selected or random colors
-    pub fn change_color(
-        &mut self,
-        model: &mut Model,
-        screen: &mut Screen,
-        info: &mut Info,
-        rcolor: &mut RevertColor,
-        csliders: &mut Csliders,
-    ) {
-        let untouched_color = Image::gen_image_color(90, 60, model.hues.untouched);
-        let untouched_texture = Texture2D::from_image(&untouched_color);
-        let zero_color = Image::gen_image_color(90, 60, model.hues.zero_grains);
-        let zero_texture = Texture2D::from_image(&zero_color);
-        let one_color = Image::gen_image_color(90, 60, model.hues.one_grain);
-        let one_texture = Texture2D::from_image(&one_color);
-        let two_color = Image::gen_image_color(90, 60, model.hues.two_grains);
-        let two_texture = Texture2D::from_image(&two_color);
-        let three_color = Image::gen_image_color(90, 60, model.hues.three_grains);
-        let three_texture = Texture2D::from_image(&three_color);
-
-        let slider_color: Color =
-            Color::new(csliders.red, csliders.green, csliders.blue, csliders.alpha);
-        let selected_color = Image::gen_image_color(90, 60, slider_color);
-        let selected_texture = Texture2D::from_image(&selected_color);
-
+    /// new_simulation() lets the user choose the dimensions of the lattice before starting over
+    pub fn new_simulation(&mut self, screen: &Screen, info: &mut Info) {
         let w_width: f32 = 392.0;
-        let w_height: f32 = 275.0;
+        let w_height: f32 = 348.0;
         let w_tlx = (screen.width - w_width) / 2.0;
         let w_tly = (screen.height - w_height) / 2.0;
         widgets::Window::new(hash!(), vec2(w_tlx, w_tly), vec2(w_width, w_height))
-            .label("Colors")
-            .ui(&mut *root_ui(), |ui| {
-                ui.label(None, "   Null       Zero        One       Two       Three");
-                if widgets::Button::new(untouched_texture)
-                    .size(vec2(75., 26.))
+            .label("New Simulation")
+            .ui(&mut root_ui(), |ui| {
+                ui.label(
+                    None,
+                    "    Adjust sliders to set the size of the lattice and the grains needed to collapse",
+                );
+                // clicking an edge button cycles through open, closed, and periodic
+                if widgets::Button::new(format!("Left/Right: {}", self.lattice_boundary_x))
+                    .size(vec2(170., 22.))
                     .ui(ui)
                 {
-                    csliders.selected = Selected::Null;
-                    csliders.red = model.hues.untouched.r;
-                    csliders.green = model.hues.untouched.g;
-                    csliders.blue = model.hues.untouched.b;
-                    csliders.alpha = model.hues.untouched.a;
+                    self.lattice_boundary_x = self.lattice_boundary_x.next();
                 }
                 ui.same_line(0.);
-                if widgets::Button::new(zero_texture)
-                    .size(vec2(75., 26.))
+                if widgets::Button::new(format!("Top/Bottom: {}", self.lattice_boundary_y))
+                    .size(vec2(170., 22.))
                     .ui(ui)
                 {
-                    csliders.selected = Selected::Zero;
-                    csliders.red = model.hues.zero_grains.r;
-                    csliders.green = model.hues.zero_grains.g;
-                    csliders.blue = model.hues.zero_grains.b;
-                    csliders.alpha = model.hues.zero_grains.a;
+                    self.lattice_boundary_y = self.lattice_boundary_y.next();
                 }
-                ui.same_line(0.);
-                if widgets::Button::new(one_texture)
-                    .size(vec2(75., 26.))
+                // changing the lattice starts from its natural threshold - one grain per neighbor
+                if widgets::Button::new(format!("Lattice: {}", self.lattice_shape))
+                    .size(vec2(170., 22.))
                     .ui(ui)
                 {
-                    csliders.selected = Selected::One;
-                    csliders.red = model.hues.one_grain.r;
-                    csliders.green = model.hues.one_grain.g;
-                    csliders.blue = model.hues.one_grain.b;
-                    csliders.alpha = model.hues.one_grain.a;
+                    self.lattice_shape = self.lattice_shape.next();
+                    self.lattice_critical = self.lattice_shape.neighbors() as f32;
                 }
                 ui.same_line(0.);
-                if widgets::Button::new(two_texture)
-                    .size(vec2(75., 26.))
+                if widgets::Button::new(format!("Rule: {}", self.lattice_rule))
+                    .size(vec2(170., 22.))
                     .ui(ui)
                 {
-                    csliders.selected = Selected::Two;
-                    csliders.red = model.hues.two_grains.r;
-                    csliders.green = model.hues.two_grains.g;
-                    csliders.blue = model.hues.two_grains.b;
-                    csliders.alpha = model.hues.two_grains.a;
+                    self.lattice_rule = self.lattice_rule.next();
+                }
+                ui.slider(
+                    hash!(),
+                    "  Width",
+                    MIN_LATTICE..MAX_LATTICE,
+                    &mut self.lattice_width,
+                );
+                ui.slider(
+                    hash!(),
+                    "  Height",
+                    MIN_LATTICE..MAX_LATTICE,
+                    &mut self.lattice_height,
+                );
+                ui.slider(
+                    hash!(),
+                    "  Critical",
+                    self.lattice_shape.neighbors() as f32..MAX_CRITICAL as f32 + 1.0,
+                    &mut self.lattice_critical,
+                );
+                self.lattice_width = self.lattice_width.round();
+                self.lattice_height = self.lattice_height.round();
+                self.lattice_critical = self
+                    .lattice_critical
+                    .floor()
+                    .clamp(self.lattice_shape.neighbors() as f32, MAX_CRITICAL as f32);
+                // random thresholds can't go past MAX_CRITICAL
+                ui.slider(
+                    hash!(),
+                    "  Spread",
+                    0.0..(MAX_CRITICAL as f32 - self.lattice_critical + 1.0),
+                    &mut self.lattice_spread,
+                );
+                ui.slider(
+                    hash!(),
+                    "  Dissipation",
+                    0.0..1.0,
+                    &mut self.lattice_dissipation,
+                );
+                // OFC conserves energy when each neighbor receives 1 / neighbors of it
+                ui.slider(
+                    hash!(),
+                    "  Alpha",
+                    0.0..1.0 / self.lattice_shape.neighbors() as f32,
+                    &mut self.lattice_alpha,
+                );
+                ui.input_text(hash!(), "  Seed", &mut self.lattice_seed);
+                self.lattice_spread = self
+                    .lattice_spread
+                    .floor()
+                    .min(MAX_CRITICAL as f32 - self.lattice_critical);
+                self.lattice_dissipation = (self.lattice_dissipation * 1_000.0).round() / 1_000.0;
+                self.lattice_alpha = ((self.lattice_alpha * 1_000.0).round() / 1_000.0)
+                    .min(1.0 / self.lattice_shape.neighbors() as f32);
+                ui.label(
+                    None,
+                    &format!(
+                        "    {} x {} lattice = {} cells - collapse at {} grains",
+                        self.lattice_width,
+                        self.lattice_height,
+                        self.lattice_width * self.lattice_height,
+                        self.lattice_critical
+                    ),
+                );
+                ui.label(
+                    None,
+                    &format!(
+                        "    thresholds {} to {} - {:.1}% of grains dissipated",
+                        self.lattice_critical,
+                        self.lattice_critical + self.lattice_spread,
+                        self.lattice_dissipation * 100.0
+                    ),
+                );
+                if widgets::Button::new("Start").size(vec2(75., 26.)).ui(ui) {
+                    if self.lattice_seed.trim().parse::<u64>().is_ok() {
+                        self.reset = true;
+                        self.setup = false;
+                    } else {
+                        info.context = "The seed must be a whole number".to_string();
+                    }
                 }
                 ui.same_line(0.);
-                if widgets::Button::new(three_texture)
-                    .size(vec2(75., 26.))
+                if widgets::Button::new("Default").size(vec2(75., 26.)).ui(ui) {
+                    self.lattice_width = MODEL_WIDTH as f32;
+                    self.lattice_height = MODEL_HEIGHT as f32;
+                    self.lattice_critical = Lattice::Square.neighbors() as f32;
+                    self.lattice_boundary_x = Boundary::Open;
+                    self.lattice_boundary_y = Boundary::Open;
+                    self.lattice_shape = Lattice::Square;
+                    self.lattice_rule = Rule::Btw;
+                    self.lattice_spread = 0.0;
+                    self.lattice_dissipation = 0.0;
+                    self.lattice_seed = SEED.to_string();
+                    self.lattice_alpha = ALPHA;
+                }
+                ui.same_line(0.);
+                if widgets::Button::new("Cancel").size(vec2(75., 26.)).ui(ui) {
+                    self.setup = false;
+                    info.context = "<--Click here to hide the control panel".to_string();
+                }
+                if IO_SUPPORTED {
+                    ui.same_line(0.);
+                    if widgets::Button::new("Load...").size(vec2(75., 26.)).ui(ui) {
+                        self.setup = false;
+                        self.show_saved();
+                    }
+                }
+            });
+    }
+    /// load_model() lists the saved models found in the folders and loads the one that is clicked
+    pub fn load_model(&mut self, screen: &Screen, info: &mut Info) {
+        let w_width: f32 = 560.0;
+        let w_height: f32 = 360.0;
+        let w_tlx = (screen.width - w_width) / 2.0;
+        let w_tly = (screen.height - w_height) / 2.0;
+        widgets::Window::new(hash!(), vec2(w_tlx, w_tly), vec2(w_width, w_height))
+            .label("Load Simulation")
+            .ui(&mut root_ui(), |ui| {
+                let folders: Vec<String> = self
+                    .folders
+                    .iter()
+                    .map(|folder| folder.display().to_string())
+                    .collect();
+                ui.label(None, &format!("    Saved models in {}", folders.join(", ")));
+                widgets::Group::new(hash!(), vec2(w_width - 10.0, 250.0)).ui(ui, |ui| {
+                    if self.saved.is_empty() {
+                        ui.label(None, "    No .lak, .lkb, or .journal files found");
+                    }
+                    // the size of each model comes from its header so nothing is loaded until it is chosen
+                    for saved in self.saved.iter() {
+                        let name = saved
+                            .path
+                            .file_name()
+                            .map_or(String::new(), |name| name.to_string_lossy().to_string());
+                        let label = format!(
+                            "{:<30} {:>5} x {:<5} {:>10} grains  {} {}",
+                            name,
+                            saved.width,
+                            saved.height,
+                            saved.total_grains,
+                            saved.lattice,
+                            saved.rule
+                        );
+                        if widgets::Button::new(label)
+                            .size(vec2(w_width - 30.0, 22.))
+                            .ui(ui)
+                        {
+                            self.load = Some(saved.path.clone());
+                        }
+                    }
+                });
+                ui.input_text(hash!(), "  Folder", &mut self.folder);
+                if widgets::Button::new("Add folder")
+                    .size(vec2(90., 26.))
                     .ui(ui)
                 {
-                    csliders.selected = Selected::Three;
-                    csliders.red = model.hues.three_grains.r;
-                    csliders.green = model.hues.three_grains.g;
-                    csliders.blue = model.hues.three_grains.b;
-                    csliders.alpha = model.hues.three_grains.a;
+                    let folder = PathBuf::from(self.folder.trim());
+                    if folder.is_dir() {
+                        self.open(&folder, info);
+                        self.folder.clear();
+                        self.saved = SavedModel::find(&self.folders);
+                    } else {
+                        info.context = format!("'{}' is not a folder", folder.display());
+                    }
+                }
+                ui.same_line(0.);
+                if widgets::Button::new("Refresh").size(vec2(75., 26.)).ui(ui) {
+                    self.saved = SavedModel::find(&self.folders);
+                }
+                ui.same_line(0.);
+                if widgets::Button::new("Cancel").size(vec2(75., 26.)).ui(ui) {
+                    self.picker = false;
+                    info.context = "<--Click here to hide the control panel".to_string();
+                }
+            });
+        if self.load.is_some() {
+            self.picker = false;
+        }
+    }
+    /// draw_distributions() plots log-binned avalanche sizes and durations on log-log axes with power law fits
+    pub fn draw_distributions(&mut self, model: &Model, screen: &Screen) {
+        let sizes = Histogram::log_bins(&model.histogram.topples);
+        let durations = Histogram::log_bins(&model.histogram.duration);
+        let size_fit = PowerLaw::fit(&model.histogram.topples, FIT_MIN);
+        let duration_fit = PowerLaw::fit(&model.histogram.duration, FIT_MIN);
+        // axes cover whole decades - x from 1 to the largest bin, y from the smallest density to 1
+        let x_decades = sizes
+            .iter()
+            .chain(durations.iter())
+            .map(|(center, _)| center.log10().ceil())
+            .fold(1.0_f64, f64::max);
+        let y_decades = sizes
+            .iter()
+            .chain(durations.iter())
+            .map(|(_, density)| -density.log10().floor())
+            .fold(1.0_f64, f64::max);
+        let w_width: f32 = 320.0;
+        let w_height: f32 = 290.0;
+        let w_tlx = screen.width - w_width - 5.0;
+        let w_tly = 25.0;
+        widgets::Window::new(hash!(), vec2(w_tlx, w_tly), vec2(w_width, w_height))
+            .label("Avalanches")
+            .ui(&mut root_ui(), |ui| {
+                let mut canvas = ui.canvas();
+                let cursor = canvas.cursor();
+                let plot = Rect::new(cursor.x + 10.0, cursor.y + 5.0, w_width - 30.0, 170.0);
+                canvas.rect(plot, Some(Color::new(0.5, 0.5, 0.5, 1.0).into()), None);
+                // tick marks at every decade
+                for decade in 1..x_decades as usize {
+                    let x = plot.x + plot.w * decade as f32 / x_decades as f32;
+                    canvas.line(
+                        vec2(x, plot.y + plot.h - 4.0),
+                        vec2(x, plot.y + plot.h),
+                        GRAY,
+                    );
+                }
+                for decade in 1..y_decades as usize {
+                    let y = plot.y + plot.h * decade as f32 / y_decades as f32;
+                    canvas.line(vec2(plot.x, y), vec2(plot.x + 4.0, y), GRAY);
+                }
+                let to_plot = |(center, density): &(f64, f64)| -> Vec2 {
+                    vec2(
+                        plot.x + plot.w * (center.log10() / x_decades) as f32,
+                        plot.y + plot.h * (-density.log10() / y_decades) as f32,
+                    )
+                };
+                for (bins, color) in [(&sizes, ORANGE), (&durations, SKYBLUE)] {
+                    let points: Vec<Vec2> = bins.iter().map(to_plot).collect();
+                    for pair in points.windows(2) {
+                        canvas.line(pair[0], pair[1], color);
+                    }
+                    for point in points {
+                        canvas.rect(
+                            Rect::new(point.x - 2.0, point.y - 2.0, 4.0, 4.0),
+                            color,
+                            color,
+                        );
+                    }
+                }
+                canvas.request_space(vec2(w_width - 20.0, 180.0));
+                ui.label(
+                    None,
+                    &format!("x: 1 to 10^{}   y: 10^-{} to 1", x_decades, y_decades),
+                );
+                for (name, fit) in [
+                    ("Size (orange)", size_fit),
+                    ("Duration (blue)", duration_fit),
+                ] {
+                    match fit {
+                        Some(fit) => ui.label(
+                            None,
+                            &format!(
+                                "{}: {:.3} +/- {:.3} cutoff {:.0}",
+                                name, fit.exponent, fit.error, fit.cutoff
+                            ),
+                        ),
+                        None => ui.label(None, &format!("{}: not enough avalanches", name)),
+                    }
+                }
+                ui.label(
+                    None,
+                    &format!(
+                        "{} avalanches - fits use values >= {}",
+                        model.histogram.avalanches(),
+                        FIT_MIN
+                    ),
+                );
+            });
+    }
+    /// draw_patterns() shows the symmetry of each pile, the periods near the seams, and the dimensions of the threads
+    pub fn draw_patterns(&mut self, model: &Model, screen: &Screen, info: &mut Info) {
+        let Some(patterns) = self.patterns.clone() else {
+            return;
+        };
+        let w_width: f32 = 320.0;
+        let w_height: f32 = 330.0;
+        let w_tlx = screen.width - w_width - 5.0;
+        let w_tly = screen.height - w_height - 5.0;
+        widgets::Window::new(hash!(), vec2(w_tlx, w_tly), vec2(w_width, w_height))
+            .label(&format!(
+                "Patterns at {} sand grains",
+                patterns.total_grains
+            ))
+            .ui(&mut root_ui(), |ui| {
+                ui.label(None, "D4 symmetry around each drop cell");
+                for symmetry in patterns.symmetry.iter() {
+                    ui.label(
+                        None,
+                        &format!(
+                            "  {:2} at ({}, {}): {:.4} within {}",
+                            symmetry.pile, symmetry.x, symmetry.y, symmetry.score, symmetry.radius
+                        ),
+                    );
+                }
+                ui.label(None, "Strongest periods near each seam");
+                for period in patterns.periods.iter() {
+                    ui.label(
+                        None,
+                        &format!(
+                            "  {}-{}: {:5.1} cells at {:5.1} deg {:4.1}%",
+                            period.piles.0,
+                            period.piles.1,
+                            period.period,
+                            period.direction,
+                            period.power * 100.0
+                        ),
+                    );
+                }
+                ui.label(None, "Box-counting dimension of the threads");
+                for dimension in patterns.dimensions.iter() {
+                    let value = dimension
+                        .dimension
+                        .map_or("too small".to_string(), |dimension| {
+                            format!("{:.3}", dimension)
+                        });
+                    ui.label(None, &format!("  {} grains: {}", dimension.label(), value));
+                }
+                if ui.button(None, "Measure again") {
+                    self.patterns = Some(Patterns::measure(model));
+                }
+                ui.same_line(0.);
+                if ui.button(None, "Export") {
+                    let filename = format!("Lakhesis_{:08}_patterns.csv", patterns.total_grains);
+                    info.context = self.export_patterns(model, &filename);
+                }
+                ui.same_line(0.);
+                if ui.button(None, "Close") {
+                    self.patterns = None;
+                }
+            });
+    }
+    /// export_patterns() saves the patterns as CSV - measured again first if the lattice has changed since
+    pub fn export_patterns(&mut self, model: &Model, filename: &str) -> String {
+        if self
+            .patterns
+            .as_ref()
+            .is_none_or(|patterns| patterns.total_grains != model.total_grains)
+        {
+            self.patterns = Some(Patterns::measure(model));
+        }
+        let csv = self
+            .patterns
+            .as_ref()
+            .map_or(String::new(), Patterns::to_csv);
+        match std::fs::write(filename, csv) {
+            Ok(()) => format!("{} exported", filename),
+            Err(why) => format!("Error writing {}: {}", filename, why),
+        }
+    }
+    /// draw_growth() plots the radius of each pile against the grains it has received on log-log axes - a lone pile
+    /// follows a line of slope 1/2
+    pub fn draw_growth(&mut self, model: &Model, screen: &Screen, info: &mut Info) {
+        // axes cover whole decades - x from 1 to the most grains, y from 1 to the largest radius
+        let x_decades = model
+            .growth
+            .iter()
+            .map(|growth| (growth.grains.max(1) as f64).log10().ceil())
+            .fold(1.0_f64, f64::max);
+        let y_decades = model
+            .growth
+            .iter()
+            .map(|growth| (growth.radius.max(1.0) as f64).log10().ceil())
+            .fold(1.0_f64, f64::max);
+        let w_width: f32 = 320.0;
+        let w_height: f32 = 330.0;
+        let w_tlx = screen.width - 2.0 * (w_width + 5.0);
+        let w_tly = 25.0;
+        widgets::Window::new(hash!(), vec2(w_tlx, w_tly), vec2(w_width, w_height))
+            .label("Growth of each pile")
+            .ui(&mut root_ui(), |ui| {
+                let mut canvas = ui.canvas();
+                let cursor = canvas.cursor();
+                let plot = Rect::new(cursor.x + 10.0, cursor.y + 5.0, w_width - 30.0, 150.0);
+                canvas.rect(plot, Some(Color::new(0.5, 0.5, 0.5, 1.0).into()), None);
+                for decade in 1..x_decades as usize {
+                    let x = plot.x + plot.w * decade as f32 / x_decades as f32;
+                    canvas.line(
+                        vec2(x, plot.y + plot.h - 4.0),
+                        vec2(x, plot.y + plot.h),
+                        GRAY,
+                    );
+                }
+                for decade in 1..y_decades as usize {
+                    let y = plot.y + plot.h - plot.h * decade as f32 / y_decades as f32;
+                    canvas.line(vec2(plot.x, y), vec2(plot.x + 4.0, y), GRAY);
+                }
+                let to_plot = |(grains, radius): (f64, f64)| -> Vec2 {
+                    vec2(
+                        plot.x + plot.w * (grains.log10() / x_decades) as f32,
+                        plot.y + plot.h - plot.h * (radius.log10() / y_decades) as f32,
+                    )
+                };
+                // the square root law from the origin for comparison
+                canvas.line(
+                    to_plot((1.0, 1.0)),
+                    to_plot((10_f64.powf(x_decades), 10_f64.powf(x_decades / 2.0))),
+                    DARKGRAY,
+                );
+                for (i, growth) in model.growth.iter().enumerate() {
+                    let color = Hues::source_hue(i).into();
+                    let points: Vec<Vec2> = growth
+                        .curve
+                        .iter()
+                        .map(|&(grains, radius)| to_plot((grains as f64, radius as f64)))
+                        .collect();
+                    for pair in points.windows(2) {
+                        canvas.line(pair[0], pair[1], color);
+                    }
                 }
+                canvas.request_space(vec2(w_width - 20.0, 160.0));
                 ui.label(
                     None,
                     &format!(
-                        "              {:?}-grain color selected",
-                        &csliders.selected
+                        "x: grains 1 to 10^{}   y: radius 1 to 10^{}",
+                        x_decades, y_decades
                     ),
                 );
+                // the first piles fit in the window - the export lists them all
+                for (i, growth) in model.growth.iter().enumerate().take(GROWTH_ROWS) {
+                    let exponent = growth
+                        .exponent()
+                        .map_or("-".to_string(), |exponent| format!("{:.3}", exponent));
+                    ui.label(
+                        None,
+                        &format!(
+                            "{:2}: {:9} grains r {:6.1} area {:8} exp {}",
+                            i, growth.grains, growth.radius, growth.area, exponent
+                        ),
+                    );
+                }
+                if model.growth.len() > GROWTH_ROWS {
+                    ui.label(
+                        None,
+                        &format!("and {} more piles", model.growth.len() - GROWTH_ROWS),
+                    );
+                }
+                if ui.button(None, "Export") {
+                    let name = format!("Lakhesis_{:08}_growth", model.total_grains);
+                    info.context =
+                        match std::fs::write(format!("{}.csv", name), Growth::to_csv(model))
+                            .and_then(|()| {
+                                std::fs::write(
+                                    format!("{}_curve.csv", name),
+                                    Growth::curves_csv(model),
+                                )
+                            }) {
+                            Ok(()) => format!("{}.csv and {}_curve.csv exported", name, name),
+                            Err(why) => format!("Error writing {}: {}", name, why),
+                        };
+                }
+                ui.same_line(0.);
+                if ui.button(None, "Close") {
+                    self.growth = false;
+                }
+            });
+    }
+    /// draw_piles() marks each drop cell on the lattice and lists them in a window with buttons to pause or resume,
+    /// change the rate of, and delete each one - every change is logged so rewinding and journals repeat it
+    pub fn draw_piles(
+        &mut self,
+        model: &mut Model,
+        history: &mut History,
+        screen: &Screen,
+        info: &mut Info,
+    ) {
+        // paused piles are marked in gray and the pile being dragged follows the mouse
+        for i in 0..model.active_cells {
+            let (sx, sy) = match self.dragging {
+                Some(slot) if slot == i => mouse_position(),
+                _ => pile_xy(model, screen, i),
+            };
+            let color = match model.is_dropping(i) {
+                true => Hues::source_hue(i).into(),
+                false => GRAY,
+            };
+            draw_circle_lines(sx, sy, MARKER, 2.0, color);
+            draw_text(&i.to_string(), sx + MARKER, sy - MARKER, 16.0, color);
+        }
+        let (mut remove, mut toggle, mut rate) = (None, None, None);
+        let w_width: f32 = 320.0;
+        let w_height: f32 = 330.0;
+        let w_tlx = 240.0;
+        let w_tly = 25.0;
+        widgets::Window::new(hash!(), vec2(w_tlx, w_tly), vec2(w_width, w_height))
+            .label("Sandpiles")
+            .ui(&mut root_ui(), |ui| {
+                ui.label(None, "Drag a marker on the lattice to move its pile");
+                for i in 0..model.active_cells {
+                    let (x, y) = model.idx_to_xy(model.drop_cells[i]);
+                    let schedule = model.schedules[i];
+                    let paused = schedule.paused.is_some();
+                    ui.label(
+                        None,
+                        &format!(
+                            "{:2} ({:4}, {:4}) x{:<3} {}",
+                            i,
+                            x,
+                            y,
+                            schedule.rate,
+                            match (paused, model.is_dropping(i)) {
+                                (true, _) => "paused",
+                                (false, true) => "dropping",
+                                (false, false) => "stopped",
+                            }
+                        ),
+                    );
+                    if ui.button(None, "-") && schedule.rate > 1 {
+                        rate = Some((i, schedule.rate - 1));
+                    }
+                    ui.same_line(0.);
+                    if ui.button(None, "+") {
+                        rate = Some((i, schedule.rate.saturating_add(1)));
+                    }
+                    ui.same_line(0.);
+                    if ui.button(None, if paused { "Resume" } else { "Pause" }) {
+                        toggle = Some(i);
+                    }
+                    ui.same_line(0.);
+                    if ui.button(None, "Delete") {
+                        remove = Some(i);
+                    }
+                }
+                if model.active_cells == 0 {
+                    ui.label(None, "There are no sandpiles - press [A] to add one");
+                }
+                if ui.button(None, "Close") {
+                    self.piles = false;
+                }
+            });
+        if let Some((i, rate)) = rate {
+            history.rate(model, i, rate);
+            info.context = format!(
+                "Sandpile {} receives {} grains each time its turn comes around",
+                i, rate
+            );
+        }
+        if let Some(i) = toggle {
+            match model.schedules[i].paused {
+                None => {
+                    history.pause(model, i);
+                    info.context = format!("Sandpile {} paused", i);
+                }
+                Some(_) => {
+                    history.resume(model, i);
+                    info.context = format!("Sandpile {} resumed", i);
+                }
+            }
+        }
+        if let Some(i) = remove {
+            history.remove(model, i);
+            info.context = format!(
+                "Sandpile {} deleted - the piles after it are numbered one lower",
+                i
+            );
+        }
+    }
+    /// drag_pile() picks up the drop cell whose marker is clicked while the drop cell manager is open and moves the
+    /// pile to the cell where it is let go
+    pub fn drag_pile(
+        &mut self,
+        model: &mut Model,
+        history: &mut History,
+        screen: &Screen,
+        info: &mut Info,
+    ) {
+        if !self.piles {
+            self.dragging = None;
+            return;
+        }
+        let (mx, my) = mouse_position();
+        if is_mouse_button_pressed(MouseButton::Left) && !root_ui().is_mouse_over(vec2(mx, my)) {
+            self.dragging = (0..model.active_cells).rev().find(|&i| {
+                let (sx, sy) = pile_xy(model, screen, i);
+                (sx - mx).hypot(sy - my) <= MARKER
+            });
+        }
+        if let Some(slot) = self.dragging {
+            if !is_mouse_button_down(MouseButton::Left) {
+                self.dragging = None;
+                info.context = match screen.lattice_xy(model, mx, my) {
+                    Some((x, y)) => {
+                        history.relocate(model, slot, model.xy_to_idx(x, y));
+                        format!("Sandpile {} moved to ({}, {})", slot, x, y)
+                    }
+                    None => "A sandpile must stay on the lattice".to_string(),
+                };
+            }
+        }
+    }
+    /// draw_timeline() shows how far back the simulation can be rewound and moves it to the grain count chosen on
+    /// the slider once the mouse is released
+    pub fn draw_timeline(&mut self, model: &Model, history: &History, screen: &Screen) {
+        let w_width: f32 = 420.0;
+        let w_height: f32 = 130.0;
+        let w_tlx = (screen.width - w_width) / 2.0;
+        let w_tly = screen.height - w_height - 25.0;
+        widgets::Window::new(hash!(), vec2(w_tlx, w_tly), vec2(w_width, w_height))
+            .label("Timeline")
+            .ui(&mut root_ui(), |ui| {
+                ui.label(
+                    None,
+                    &format!(
+                        "    {} to {} sand grains - now at {}",
+                        history.earliest(),
+                        history.latest(),
+                        model.total_grains
+                    ),
+                );
+                let chosen = self.timeline_grains;
+                ui.slider(
+                    hash!(),
+                    "  Grains",
+                    history.earliest() as f32..history.latest().max(history.earliest() + 1) as f32,
+                    &mut self.timeline_grains,
+                );
+                if self.timeline_grains != chosen {
+                    self.scrubbing = true;
+                }
+                if widgets::Button::new("<< Interval")
+                    .size(vec2(90., 26.))
+                    .ui(ui)
+                {
+                    self.rewind = Some(model.total_grains.saturating_sub(model.interval));
+                }
+                ui.same_line(0.);
+                if widgets::Button::new("Interval >>")
+                    .size(vec2(90., 26.))
+                    .ui(ui)
+                {
+                    self.rewind = Some(model.total_grains + model.interval);
+                }
+                ui.same_line(0.);
+                if widgets::Button::new("Undo Add").size(vec2(90., 26.)).ui(ui) {
+                    self.undo = true;
+                }
+                ui.same_line(0.);
+                if widgets::Button::new("Close").size(vec2(75., 26.)).ui(ui) {
+                    self.timeline = false;
+                }
+            });
+        // replaying can take a moment so the model only follows the slider when it is let go
+        if self.scrubbing && !is_mouse_button_down(MouseButton::Left) {
+            self.rewind = Some(self.timeline_grains.round() as usize);
+            self.scrubbing = false;
+        } else if !self.scrubbing {
+            self.timeline_grains = model.total_grains as f32;
+        }
+    }
+    /// change_color() allows user selected or random colors
+    pub fn change_color(
+        &mut self,
+        model: &mut Model,
+        screen: &mut Screen,
+        info: &mut Info,
+        rcolor: &mut RevertColor,
+        csliders: &mut Csliders,
+    ) {
+        // one button for untouched cells and one for each grain count a stable cell can hold
+        let mut choices: Vec<(Selected, Texture2D)> = vec![(
+            Selected::Null,
+            Texture2D::from_image(&Image::gen_image_color(90, 60, model.hues.untouched.into())),
+        )];
+        for grains in 0..model.critical as usize {
+            choices.push((
+                Selected::Grains(grains),
+                Texture2D::from_image(&Image::gen_image_color(
+                    90,
+                    60,
+                    model.hues.grains[grains].into(),
+                )),
+            ));
+        }
+
+        let slider_color: Color =
+            Color::new(csliders.red, csliders.green, csliders.blue, csliders.alpha);
+        let selected_color = Image::gen_image_color(90, 60, slider_color.into());
+        let selected_texture = Texture2D::from_image(&selected_color);
+
+        // five buttons fit on each row - thresholds above four need a second row
+        let rows = choices.len().div_ceil(5);
+        let w_width: f32 = 392.0;
+        let w_height: f32 = 275.0 + 45.0 * (rows - 1) as f32;
+        let w_tlx = (screen.width - w_width) / 2.0;
+        let w_tly = (screen.height - w_height) / 2.0;
+        widgets::Window::new(hash!(), vec2(w_tlx, w_tly), vec2(w_width, w_height))
+            .label("Colors")
+            .ui(&mut root_ui(), |ui| {
+                for (row, row_choices) in choices.chunks(5).enumerate() {
+                    let names: String = HUE_NAMES[row * 5..row * 5 + row_choices.len()]
+                        .iter()
+                        .map(|name| format!("{:^11}", name))
+                        .collect();
+                    ui.label(None, &names);
+                    for (column, (selected, texture)) in row_choices.iter().enumerate() {
+                        if column > 0 {
+                            ui.same_line(0.);
+                        }
+                        if widgets::Button::new(*texture).size(vec2(75., 26.)).ui(ui) {
+                            csliders.pick(*selected, &model.hues);
+                        }
+                    }
+                }
+                let selected_name = match csliders.selected {
+                    Selected::Null => "Null".to_string(),
+                    Selected::Grains(grains) => format!("{}-grain", grains),
+                };
+                ui.label(
+                    None,
+                    &format!("              {} color selected", &selected_name),
+                );
                 ui.label(None, "     ");
                 ui.same_line(0.0);
                 if widgets::Button::new(selected_texture)
@@ -482,47 +1457,8 @@ impl Control {
                     .ui(ui)
                 {
                     match csliders.selected {
-                        Selected::Null => {
-                            model.hues.untouched = Color::new(
-                                csliders.red,
-                                csliders.green,
-                                csliders.blue,
-                                csliders.alpha,
-                            );
-                        }
-                        Selected::Zero => {
-                            model.hues.zero_grains = Color::new(
-                                csliders.red,
-                                csliders.green,
-                                csliders.blue,
-                                csliders.alpha,
-                            );
-                        }
-                        Selected::One => {
-                            model.hues.one_grain = Color::new(
-                                csliders.red,
-                                csliders.green,
-                                csliders.blue,
-                                csliders.alpha,
-                            );
-                        }
-                        Selected::Two => {
-                            model.hues.two_grains = Color::new(
-                                csliders.red,
-                                csliders.green,
-                                csliders.blue,
-                                csliders.alpha,
-                            );
-                        }
-                        Selected::Three => {
-                            model.hues.three_grains = Color::new(
-                                csliders.red,
-                                csliders.green,
-                                csliders.blue,
-                                csliders.alpha,
-                            );
-                        }
-                        _ => (),
+                        Selected::Null => model.hues.untouched = slider_color,
+                        Selected::Grains(grains) => model.hues.grains[grains] = slider_color,
                     }
                     info.context= "After editing individual grain-colors click 'Accept' to restart simulation".to_string();
                 }
@@ -535,11 +1471,7 @@ impl Control {
                 ui.label(None, " Null = background     An alpha of 0.00 is transparent");
                 if widgets::Button::new("Accept").size(vec2(75., 26.)).ui(ui) {
                     *rcolor = RevertColor::default(model);
-                    csliders.selected = Selected::One;
-                    csliders.red = model.hues.one_grain.r;
-                    csliders.green = model.hues.one_grain.g;
-                    csliders.blue = model.hues.one_grain.b;
-                    csliders.alpha = model.hues.one_grain.a;
+                    csliders.pick(Selected::default(), &model.hues);
                     info.context = "<--Click here to hide the control panel".to_string();
                     self.color = false;
                     self.paused = false;
@@ -547,44 +1479,25 @@ impl Control {
                 ui.same_line(0.);
                 if widgets::Button::new("Revert").size(vec2(75., 26.)).ui(ui) {
                     model.hues.untouched = rcolor.untouched;
-                    model.hues.zero_grains = rcolor.zero_grains;
-                    model.hues.one_grain = rcolor.one_grain;
-                    model.hues.two_grains = rcolor.two_grains;
-                    model.hues.three_grains = rcolor.three_grains;
+                    model.hues.grains = rcolor.grains;
                     info.context = "Click 'Accept' to save previous colors and exit or make more changes".to_string();
-                    csliders.selected = Selected::One;
-                    csliders.red = model.hues.one_grain.r;
-                    csliders.green = model.hues.one_grain.g;
-                    csliders.blue = model.hues.one_grain.b;
-                    csliders.alpha = model.hues.one_grain.a;
+                    csliders.pick(Selected::default(), &model.hues);
                 }
                 ui.same_line(0.);
                 if widgets::Button::new("Random").size(vec2(75., 26.)).ui(ui) {
                     model.random_colors();
                     info.context = "Click 'Accept' to save the random colors and exit, make more changes, or 'Revert' to go back to last selection".to_string();
-                    csliders.selected = Selected::One;
-                    csliders.red = model.hues.one_grain.r;
-                    csliders.green = model.hues.one_grain.g;
-                    csliders.blue = model.hues.one_grain.b;
-                    csliders.alpha = model.hues.one_grain.a;
+                    csliders.pick(Selected::default(), &model.hues);
                 }
                 ui.same_line(0.);
                 if widgets::Button::new("Default").size(vec2(75., 26.)).ui(ui) {
                     model.hues = Hues::default();
                     info.context = "Click 'Accept' to save default colors and exit, make more changes, or 'Revert' to go back to last selection".to_string();
-                    csliders.selected = Selected::One;
-                    csliders.red = model.hues.one_grain.r;
-                    csliders.green = model.hues.one_grain.g;
-                    csliders.blue = model.hues.one_grain.b;
-                    csliders.alpha = model.hues.one_grain.a;
+                    csliders.pick(Selected::default(), &model.hues);
                 }
                 ui.same_line(0.);
                 if widgets::Button::new("Cancel").size(vec2(75., 26.)).ui(ui) {
-                    csliders.selected = Selected::One;
-                    csliders.red = model.hues.one_grain.r;
-                    csliders.green = model.hues.one_grain.g;
-                    csliders.blue = model.hues.one_grain.b;
-                    csliders.alpha = model.hues.one_grain.a;
+                    csliders.pick(Selected::default(), &model.hues);
                     info.context = "<--Click here to hide the control panel".to_string();
                     self.color = false;
                     self.paused = false;
@@ -592,3 +1505,10 @@ impl Control {
             });
     }
 }
+
+// pile_xy() returns the screen position of the center of drop cell i
+fn pile_xy(model: &Model, screen: &Screen, i: usize) -> (f32, f32) {
+    let (x, y) = model.idx_to_xy(model.drop_cells[i]);
+    let (sx, sy) = screen.screen_xy(model, x, y);
+    (sx + screen.zoom / 2.0, sy + screen.zoom / 2.0)
+}
@@ -1,20 +1,23 @@
-use lakhesis::Screen;
-use lakhesis::{Control, Info};
-use lakhesis::{Csliders, RevertColor, Selected};
-use lakhesis::{Model, MAX_ITERATIONS};
+use lakhesis::command_line;
+#[cfg(feature = "gui")]
+use lakhesis::{
+    Control, Csliders, History, Info, Journal, Model, Operation, RevertColor, Screen, Selected,
+};
 
-use macroquad::color::colors::*;
-use macroquad::input::*;
-use macroquad::math::*;
-use macroquad::shapes::*;
-use macroquad::text::*;
-use macroquad::ui::root_ui;
-use macroquad::window::*;
+#[cfg(feature = "gui")]
+use std::path::Path;
+
+#[cfg(feature = "gui")]
+use macroquad::{
+    color::colors::*, input::*, math::*, shapes::*, text::*, ui::root_ui, window::*, Window,
+};
 
 // number of PNG frames to create 10 second video at 60fps
+#[cfg(feature = "gui")]
 const VIDEO_FRAME_COUNT: usize = 600;
 
 //marcroquad window initialization
+#[cfg(feature = "gui")]
 fn window_configuration() -> Conf {
     Conf {
         window_title: "L A K H E S I S".to_owned(),
@@ -25,53 +28,94 @@ fn window_configuration() -> Conf {
     }
 }
 
-#[macroquad::main(window_configuration)]
-async fn main() {
+fn main() {
+    // arguments select a command line tool - without any, or with only files to open, open the window
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    // saved models, scenarios, and folders of saved models open in the window
+    #[cfg(feature = "gui")]
+    let args = match args.iter().all(|arg| Control::can_open(Path::new(arg))) {
+        true => {
+            Window::from_config(window_configuration(), simulation(args));
+            return;
+        }
+        false => args,
+    };
+    if !args.is_empty() {
+        std::process::exit(command_line(&args));
+    }
+    // a headless build only has the command line tools
+    #[cfg(not(feature = "gui"))]
+    std::process::exit(command_line(&["help".to_string()]));
+}
+
+#[cfg(feature = "gui")]
+async fn simulation(paths: Vec<String>) {
     let mut model = Model::default();
     let mut screen = Screen::default(&model);
     let mut info = Info::default();
     let mut control = Control::default();
-    let mut csliders: Csliders = Csliders {
-        selected: Selected::One,
-        red: model.hues.one_grain.r,
-        green: model.hues.one_grain.g,
-        blue: model.hues.one_grain.b,
-        alpha: model.hues.one_grain.a,
-    };
+    let mut history = History::new(&model);
+    for path in paths.iter() {
+        control.open(Path::new(path), &mut info);
+    }
+    let mut csliders: Csliders = Csliders::default();
+    csliders.pick(Selected::default(), &model.hues);
     let mut rcolor: RevertColor = RevertColor::default(&model);
     loop {
         screen.width = screen_width(); // start macroquad loop
         screen.height = screen_height(); // check screen size in case user has resized the window
-        clear_background(model.hues.untouched); // clear background using color designated for untouched cells
-        screen.draw(&model); // draw sandpile model
+        clear_background(model.hues.untouched.into()); // clear background using color designated for untouched cells
+        control.drag_pile(&mut model, &mut history, &screen, &mut info); // move a sandpile by dragging its marker
+        screen.navigate(&model, &control); // zoom with the mouse wheel and pan by dragging
+        screen.draw(&mut model); // draw sandpile model
         screen.crosshairs(&model, &control); // add lakhesis cursor on top of model
                                              // check if a new sandpile is pending and if the left mouse button is pressed
         if control.add && is_mouse_button_pressed(MouseButton::Left) {
-            model.active_cells += 1;
-            model.drop_cells[model.active_cells - 1] = model.xy_to_idx(
-                (screen.mx + screen.tlx).trunc() as usize,
-                (screen.my + screen.tly).trunc() as usize,
-            );
-            control.paused = false;
-            control.add = false;
-            info.context = "<--Click here to hide the control panel".to_string();
+            match screen.lattice_xy(&model, screen.mx, screen.my) {
+                Some((x, y)) => {
+                    // the history logs the new pile so rewinding replays it
+                    let cell = model.xy_to_idx(x, y);
+                    history.add(&mut model, cell);
+                    control.paused = false;
+                    control.add = false;
+                    info.context = "<--Click here to hide the control panel".to_string();
+                }
+                None => {
+                    info.context =
+                        "The starting point must be on the lattice - press [ESC] to cancel"
+                            .to_string();
+                }
+            }
         }
         // if !paused or spacebar pressed and a drop cell is active, drop sand grains and resolve unstable sandpiles
         if (!control.paused || control.increment) && model.active_cells > 0 {
-            for _ in 0..model.interval {
-                model.add_grain();
-                if model.active_cells - 1 > model.ac {
-                    model.ac += 1;
-                } else {
-                    model.ac = 0;
+            let before = model.total_grains;
+            let interval = model.interval;
+            history.advance(&mut model, interval);
+            // scheduled snapshots are taken at the end of the interval that reaches them
+            if control.snapshots.is_due(before, model.total_grains) {
+                let (min_x, min_y, extent_width, extent_height) = model.find_extent();
+                let filename = control.snapshots.filename(model.total_grains);
+                info.context = match model
+                    .render(min_x, min_y, extent_width, extent_height)
+                    .export_png(&filename)
+                {
+                    Ok(()) => format!("{} exported", filename),
+                    Err(why) => format!("Error writing {}: {}", filename, why),
                 };
+                // the patterns are saved alongside while their window is open
+                if control.patterns.is_some() {
+                    info.context =
+                        control.export_patterns(&model, &filename.replace(".png", "_patterns.csv"));
+                }
             }
             if control.video > 0 {
+                let (across, down) = screen.cells_visible();
                 model.paint(
                     screen.tlx.trunc() as u32,
                     screen.tly.trunc() as u32,
-                    screen.width.trunc() as u16,
-                    screen.height.trunc() as u16,
+                    across.ceil() as u16,
+                    down.ceil() as u16,
                 );
                 control.video -= 1;
                 info.context = format!(
@@ -82,16 +126,115 @@ async fn main() {
             }
             control.increment = false;
         }
-        // check if model is approaching undefined behaviour around 20M sand grains
-        if model.total_grains >= MAX_ITERATIONS {
+        // pause if the last avalanche could not end
+        if model.saturated && !control.paused {
+            control.paused = true;
+            info.context =
+                "The lattice is saturated - grains can't leave through closed or periodic edges"
+                    .to_string();
+        }
+        // pause once every sandpile has stopped
+        if model.active_cells > 0 && model.is_idle() && !control.paused {
             control.paused = true;
+            info.context = "Every sandpile has stopped - press [A] to add another".to_string();
+        }
+        // pause once the grain limit is reached - [L] doubles the limit and [CTRL][L] removes it
+        if control.limit > 0 && model.total_grains >= control.limit && !control.paused {
+            control.paused = true;
+            info.context = format!(
+                "Paused at the {} sand grain limit - press [L] to double the limit or [CTRL][L] to remove it",
+                &control.limit
+            );
         }
         // reset, if requested
         if control.reset {
-            model = Model::default();
+            model = control.new_model();
             screen = Screen::default(&model);
+            history = History::new(&model);
             info = Info::default();
-            control = Control::default();
+            if model.active_cells > 0 {
+                info.context = "Scenario loaded - press [P] to start".to_string();
+            }
+            control = control.restart();
+        }
+        // replace the simulation with a saved one, if requested
+        if let Some(path) = control.load.take() {
+            control.paused = true;
+            let filename = path.display().to_string();
+            // a journal starts again from its empty lattice and makes its changes as the grains fall
+            let loaded = match path
+                .extension()
+                .is_some_and(|extension| extension == "journal")
+            {
+                true => Journal::load(&filename)
+                    .and_then(|journal| History::from_journal(&journal))
+                    .map(|(replay, start)| {
+                        model = start;
+                        replay
+                    }),
+                false => model.uncurate(&filename).map(|()| History::new(&model)),
+            };
+            info.context = match loaded {
+                Ok(loaded) => {
+                    history = loaded;
+                    screen = Screen::default(&model);
+                    rcolor = RevertColor::default(&model);
+                    match history.latest() > model.total_grains {
+                        true => format!(
+                            "Replaying '{}' to {} sand grains - press [P] to play or [R] for the timeline",
+                            filename,
+                            history.latest()
+                        ),
+                        false => format!("Loaded '{}' - press [P] to continue", filename),
+                    }
+                }
+                Err(why) => format!("'{}' could not be loaded - {}", filename, why),
+            };
+        }
+        // rebuild the model at another grain count, if requested
+        if let Some(total) = control.rewind.take() {
+            control.paused = true;
+            let reached = history.seek(&mut model, total);
+            info.context = format!(
+                "Moved to {} sand grains - the timeline runs from {} to {}",
+                reached,
+                history.earliest(),
+                history.latest()
+            );
+        }
+        // remove the last sandpile added, if requested
+        if control.undo {
+            control.undo = false;
+            info.context = match history.undo(&mut model) {
+                Some(Operation::Add { at, cell }) => {
+                    let (x, y) = model.idx_to_xy(cell);
+                    format!(
+                        "Removed the sandpile added at ({}, {}) - rewound to {} sand grains",
+                        x, y, at
+                    )
+                }
+                Some(Operation::Remove { at, .. }) => {
+                    format!("Restored the sandpile removed at {} sand grains", at)
+                }
+                Some(operation) => format!(
+                    "Took back the sandpile change made at {} sand grains",
+                    operation.at()
+                ),
+                None => "There is no sandpile change to undo - it may be older than the timeline"
+                    .to_string(),
+            };
+        }
+        // show the timeline, if requested
+        if control.timeline {
+            control.draw_timeline(&model, &history, &screen);
+        }
+        // list the saved simulations, if requested
+        if control.picker {
+            control.load_model(&screen, &mut info);
+        }
+        // choose the lattice dimensions for a new simulation, if requested
+        if control.setup {
+            control.new_simulation(&screen, &mut info);
         }
         // change model colors, if requested
         if control.color {
@@ -104,12 +247,28 @@ async fn main() {
                 &mut csliders,
             );
         }
+        // plot the avalanche distributions, if requested
+        if control.distributions {
+            control.draw_distributions(&model, &screen);
+        }
+        // show the pattern measures, if requested
+        if control.patterns.is_some() {
+            control.draw_patterns(&model, &screen, &mut info);
+        }
+        // plot the growth of each pile, if requested
+        if control.growth {
+            control.draw_growth(&model, &screen, &mut info);
+        }
+        // manage the drop cells, if requested
+        if control.piles {
+            control.draw_piles(&mut model, &mut history, &screen, &mut info);
+        }
         // display an icon in top left corner that toggles panel visibilities
         if root_ui().button(None, "<>") {
             control.visible = !control.visible;
         }
         // update info and show control panel
-        info.update(&screen);
+        info.update(&model, &screen);
         if control.paused {
             draw_rectangle_lines(0.0, 0.0, 21.0, 23.0, 3.0, ORANGE);
         }
@@ -119,7 +278,9 @@ async fn main() {
             control.draw_panel(&mut model, &mut info, &mut screen);
         }
         // has a key been pressed?
-        control.check_keyboard(&mut model, &mut info);
+        control.check_keyboard(&mut model, &history, &mut info);
+        // a journal replays interval changes so the screen updates at the same grain counts
+        history.note_interval(&mut model);
         next_frame().await;
     }
 }
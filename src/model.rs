@@ -1,19 +1,23 @@
-use macroquad::prelude::get_time;
-use macroquad::prelude::Color;
-use macroquad::prelude::BLANK;
-use macroquad::rand::gen_range;
-use macroquad::rand::srand;
+use crate::color::BLANK;
+use crate::{Avalanche, Color, Growth, Histogram, Raster, Rng};
+
+#[cfg(feature = "gui")]
 use macroquad::texture::Image;
 
-use std::fs::File;
-use std::io::{prelude::*, BufReader};
-use std::io::{LineWriter, Write};
+use std::fmt;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-const CRITICAL: u8 = 4; // number of grains to initiate a 'collapse' of the sandpile
-const MODEL_WIDTH: usize = 3_000; // 3_000 x 3_000 grid should contain a single 16M-grain sandpile
-const MODEL_HEIGHT: usize = 3_000; // this would = 9_000_000 cells
-pub const MAX_ITERATIONS: usize = 16_777_216; // number of iterations before simulation pauses - due to stack overflow
+pub(crate) const CRITICAL: u8 = 4; // default number of grains to initiate a 'collapse' of the sandpile
+pub const MIN_CRITICAL: u8 = 3; // range of collapse thresholds - a lattice needs at least one grain per neighbor
+pub const MAX_CRITICAL: u8 = 8; // a stable cell holds at most MAX_CRITICAL - 1 grains
+pub const MODEL_WIDTH: usize = 3_000; // default 3_000 x 3_000 grid should contain a single 16M-grain sandpile
+pub const MODEL_HEIGHT: usize = 3_000; // this would = 9_000_000 cells
+pub const MAX_ITERATIONS: usize = 16_777_216; // default number of iterations before simulation pauses - adjust with [L]
 pub const MAX_DROPS: usize = 32; // maximum number of drop cells = max array size
+pub const SEED: u64 = 1_952; // default seed for the stochastic rules
+pub const ALPHA: f32 = 0.2; // default OFC coupling - 0.25 conserves energy on the square lattice
+pub const UNFED: u8 = u8::MAX; // source of a cell that hasn't been fed since sources were tracked
 
 /// A Cell is point in the lattice that accumulates sand grains
 #[derive(Clone, Copy, Debug, Default)]
@@ -30,37 +34,263 @@ impl Cell {
     }
 }
 /// Hues are the colors indicating the different states of a cell in the lattice
-#[derive(Clone, Copy, Debug, Default)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Hues {
     pub untouched: Color,
-    pub zero_grains: Color,
-    pub one_grain: Color,
-    pub two_grains: Color,
-    pub three_grains: Color,
-    pub four_grains: Color, // not needed when collapse occurs at four grains
-                            // left in for variation where collapse occurs at five grains
+    pub grains: [Color; MAX_CRITICAL as usize], // indexed by the number of grains in a cell
 }
-impl Hues {
-    pub fn default() -> Self {
+impl Default for Hues {
+    fn default() -> Self {
         let untouched: Color = Color::new(0.00, 0.00, 0.00, 0.00);
-        let zero_grains: Color = Color::new(0.00, 0.47, 0.95, 1.00);
-        let one_grain: Color = Color::new(0.00, 0.89, 0.19, 1.00);
-        let two_grains: Color = Color::new(0.99, 0.98, 0.00, 1.00);
-        let three_grains: Color = Color::new(0.00, 0.00, 0.00, 0.00);
-        let four_grains: Color = Color::new(0.90, 0.16, 0.22, 1.00);
-        Self {
-            untouched,    // BLANK
-            zero_grains,  // BLUE
-            one_grain,    // GREEN
-            two_grains,   // YELLOW
-            three_grains, // BLANK stable three grain piles make up the large triangular areas
+        let grains: [Color; MAX_CRITICAL as usize] = [
+            Color::new(0.00, 0.47, 0.95, 1.00), // BLUE
+            Color::new(0.00, 0.89, 0.19, 1.00), // GREEN
+            Color::new(0.99, 0.98, 0.00, 1.00), // YELLOW
+            // BLANK stable three grain piles make up the large triangular areas
             // set to blank to highlight 'threads' of 0, 1, and 2 grain cells
-            four_grains, // RED for version where collapse occurs at five grains
+            Color::new(0.00, 0.00, 0.00, 0.00),
+            Color::new(0.90, 0.16, 0.22, 1.00), // RED - only seen with a collapse threshold above four
+            Color::new(1.00, 0.63, 0.00, 1.00), // ORANGE
+            Color::new(0.53, 0.24, 0.75, 1.00), // PURPLE
+            Color::new(0.96, 0.96, 0.96, 1.00), // WHITE
+        ];
+        Self {
+            untouched, // BLANK
+            grains,
+        }
+    }
+}
+impl Hues {
+    /// grain_hue() returns the color of a cell holding the given number of grains
+    pub fn grain_hue(&self, grains: u8) -> Color {
+        // cells waiting to collapse share the color of the largest stable pile
+        self.grains[(grains as usize).min(MAX_CRITICAL as usize - 1)]
+    }
+    /// source_hue() returns the color of drop cell i - hues are spread around the color wheel by the golden angle
+    /// so neighboring drop cells never look alike
+    pub fn source_hue(i: usize) -> Color {
+        let hue = (i as f32 * 0.618_034).fract() * 6.0;
+        let x = 1.0 - (hue % 2.0 - 1.0).abs();
+        let (r, g, b) = match hue as usize {
+            0 => (1.0, x, 0.0),
+            1 => (x, 1.0, 0.0),
+            2 => (0.0, 1.0, x),
+            3 => (0.0, x, 1.0),
+            4 => (x, 0.0, 1.0),
+            _ => (1.0, 0.0, x),
+        };
+        Color::new(r, g, b, 1.0)
+    }
+    /// tint() mixes the color of a cell with the color of the drop cell that fed it - transparent cells take the
+    /// drop cell's color at half brightness
+    pub fn tint(hue: Color, source: usize) -> Color {
+        let tint = Hues::source_hue(source);
+        Color::new(
+            (hue.r * hue.a + tint.r) / 2.0,
+            (hue.g * hue.a + tint.g) / 2.0,
+            (hue.b * hue.a + tint.b) / 2.0,
+            1.0,
+        )
+    }
+    /// energy_hue() blends the colors on either side of a fractional number of grains
+    pub fn energy_hue(&self, position: f32) -> Color {
+        let position = position.clamp(0.0, MAX_CRITICAL as f32 - 1.0);
+        let lower = self.grains[position.floor() as usize];
+        let upper = self.grains[(position.floor() as usize + 1).min(MAX_CRITICAL as usize - 1)];
+        let t = position.fract();
+        Color::new(
+            lower.r + (upper.r - lower.r) * t,
+            lower.g + (upper.g - lower.g) * t,
+            lower.b + (upper.b - lower.b) * t,
+            lower.a + (upper.a - lower.a) * t,
+        )
+    }
+}
+/// A Boundary decides what happens to grains that are pushed across an edge of the lattice
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Boundary {
+    #[default]
+    Open, // grains fall off the 'table' and are counted as lost
+    Closed,   // grains bounce back into the collapsing cell
+    Periodic, // grains wrap around to the opposite edge
+}
+impl Boundary {
+    /// next() cycles through the boundary conditions
+    pub fn next(self) -> Self {
+        match self {
+            Boundary::Open => Boundary::Closed,
+            Boundary::Closed => Boundary::Periodic,
+            Boundary::Periodic => Boundary::Open,
+        }
+    }
+}
+impl fmt::Display for Boundary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Boundary::Open => write!(f, "open"),
+            Boundary::Closed => write!(f, "closed"),
+            Boundary::Periodic => write!(f, "periodic"),
+        }
+    }
+}
+impl FromStr for Boundary {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "open" => Ok(Boundary::Open),
+            "closed" => Ok(Boundary::Closed),
+            "periodic" => Ok(Boundary::Periodic),
+            _ => Err(format!("Unknown boundary '{}'", s)),
+        }
+    }
+}
+/// A Rule decides where the grains of a collapsing cell go
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Rule {
+    #[default]
+    Btw, // Bak, Tang & Wiesenfeld - every neighbor receives the same share
+    Manna, // each grain goes to a randomly chosen neighbor
+    Zhang, // continuous energy - a collapsing cell shares all of its energy equally with its neighbors
+    Ofc, // Olami, Feder & Christensen earthquakes - each neighbor receives alpha times the energy of the cell
+}
+impl Rule {
+    /// next() cycles through the rules
+    pub fn next(self) -> Self {
+        match self {
+            Rule::Btw => Rule::Manna,
+            Rule::Manna => Rule::Zhang,
+            Rule::Zhang => Rule::Ofc,
+            Rule::Ofc => Rule::Btw,
+        }
+    }
+    /// is_continuous() is true for rules that move energy instead of whole grains
+    pub fn is_continuous(self) -> bool {
+        matches!(self, Rule::Zhang | Rule::Ofc)
+    }
+    /// max_alpha() returns the largest coupling the rule can use on lattice - an OFC avalanche that hands out more
+    /// than 1 / neighbors of a cell's energy gains energy as it spreads and never ends
+    pub fn max_alpha(self, lattice: Lattice) -> f32 {
+        match self {
+            Rule::Ofc => 1.0 / lattice.neighbors() as f32,
+            _ => 1.0,
+        }
+    }
+}
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Rule::Btw => write!(f, "btw"),
+            Rule::Manna => write!(f, "manna"),
+            Rule::Zhang => write!(f, "zhang"),
+            Rule::Ofc => write!(f, "ofc"),
+        }
+    }
+}
+impl FromStr for Rule {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "btw" => Ok(Rule::Btw),
+            "manna" => Ok(Rule::Manna),
+            "zhang" => Ok(Rule::Zhang),
+            "ofc" => Ok(Rule::Ofc),
+            _ => Err(format!("Unknown rule '{}'", s)),
+        }
+    }
+}
+/// A Lattice is the arrangement of cells that decides which neighbors receive grains from a collapsing cell
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Lattice {
+    #[default]
+    Square, // von Neumann neighborhood - above, below, left, and right
+    Moore,      // square cells that also share grains with the four diagonal cells
+    Hexagonal,  // odd rows are shifted half a cell to the right - six neighbors
+    Triangular, // alternating up and down pointing triangles - three neighbors
+}
+// neighbor offsets (dx, dy) - hexagonal and triangular lattices depend on the row or the cell
+const SQUARE: [(isize, isize); 4] = [(0, -1), (0, 1), (-1, 0), (1, 0)];
+const MOORE: [(isize, isize); 8] = [
+    (0, -1),
+    (0, 1),
+    (-1, 0),
+    (1, 0),
+    (-1, -1),
+    (1, -1),
+    (-1, 1),
+    (1, 1),
+];
+const HEX_EVEN_ROW: [(isize, isize); 6] = [(-1, 0), (1, 0), (-1, -1), (0, -1), (-1, 1), (0, 1)];
+const HEX_ODD_ROW: [(isize, isize); 6] = [(-1, 0), (1, 0), (0, -1), (1, -1), (0, 1), (1, 1)];
+const TRIANGLE_UP: [(isize, isize); 3] = [(-1, 0), (1, 0), (0, 1)];
+const TRIANGLE_DOWN: [(isize, isize); 3] = [(-1, 0), (1, 0), (0, -1)];
+impl Lattice {
+    /// neighbors() returns the number of cells that share grains with a collapsing cell
+    pub fn neighbors(self) -> u8 {
+        self.offsets(0, 0).len() as u8
+    }
+    /// offsets() returns the relative positions of the neighbors of cell (x, y)
+    pub fn offsets(self, x: usize, y: usize) -> &'static [(isize, isize)] {
+        match self {
+            Lattice::Square => &SQUARE,
+            Lattice::Moore => &MOORE,
+            Lattice::Hexagonal if y.is_multiple_of(2) => &HEX_EVEN_ROW,
+            Lattice::Hexagonal => &HEX_ODD_ROW,
+            Lattice::Triangular if (x + y).is_multiple_of(2) => &TRIANGLE_UP,
+            Lattice::Triangular => &TRIANGLE_DOWN,
+        }
+    }
+    /// next() cycles through the lattices
+    pub fn next(self) -> Self {
+        match self {
+            Lattice::Square => Lattice::Moore,
+            Lattice::Moore => Lattice::Hexagonal,
+            Lattice::Hexagonal => Lattice::Triangular,
+            Lattice::Triangular => Lattice::Square,
+        }
+    }
+}
+impl fmt::Display for Lattice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Lattice::Square => write!(f, "square"),
+            Lattice::Moore => write!(f, "moore"),
+            Lattice::Hexagonal => write!(f, "hexagonal"),
+            Lattice::Triangular => write!(f, "triangular"),
+        }
+    }
+}
+impl FromStr for Lattice {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "square" => Ok(Lattice::Square),
+            "moore" => Ok(Lattice::Moore),
+            "hexagonal" => Ok(Lattice::Hexagonal),
+            "triangular" => Ok(Lattice::Triangular),
+            _ => Err(format!("Unknown lattice '{}'", s)),
+        }
+    }
+}
+/// A Schedule sets how many grains in a row a drop cell receives and the grain counts at which it starts and stops
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Schedule {
+    pub rate: u32,             // grains dropped on the cell each time its turn comes around
+    pub start: usize,          // total grains on the lattice before the cell begins dropping
+    pub stop: Option<usize>,   // total grains at which the cell stops dropping - None to never stop
+    pub paused: Option<usize>, // total grains when the cell was paused by hand - None unless it is paused
+}
+impl Default for Schedule {
+    fn default() -> Self {
+        Self {
+            rate: 1,
+            start: 0,
+            stop: None,
+            paused: None,
         }
     }
 }
 /// A model represents the lattice on which sandpiles form
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug)]
 pub struct Model {
     pub cells: Vec<Cell>,    // 1D vec of cells indexed by total width * y + x
     pub width: usize,        // width of 'table' that sand falls on
@@ -69,37 +299,85 @@ pub struct Model {
     pub lost_grains: usize, // current quantity of sand grains that have fallen off 'table'
     pub drop_cells: [usize; MAX_DROPS], // idx of each active cell - up to 32
     pub ac: usize,     // current active cell
+    pub schedules: [Schedule; MAX_DROPS], // when and how fast each drop cell receives grains
+    pub turn: u32,     // grains the current active cell has received since its turn began
     pub active_cells: usize, // total number of active cells
     pub hues: Hues,
     pub interval: usize,
-    pub avalanche: usize, // for future implementation
+    pub avalanche: Avalanche, // collapses caused by the most recent grain
+    pub histogram: Histogram, // running counts of every avalanche
+    pub growth: Vec<Growth>, // size of the pile fed by each drop cell - empty until its first grain
+    pub critical: u8, // number of grains that causes a cell to collapse - lattice.neighbors()..=MAX_CRITICAL
+    pub boundary_x: Boundary, // left and right edges
+    pub boundary_y: Boundary, // top and bottom edges
+    pub lattice: Lattice,
+    pub rule: Rule,
+    pub spread: u8, // each cell collapses at a random threshold from critical to critical + spread - 0 for none
+    pub dissipation: f32, // probability that a grain sent to a neighbor is lost instead
+    pub seed: u64,  // starting point of rng - the same seed repeats the same stochastic model
+    pub rng: Rng,
+    pub thresholds: Vec<u8>, // collapse threshold of each cell - empty unless spread > 0
+    pub energy: Vec<f32>,    // energy of each cell for the continuous rules - empty otherwise
+    pub lost_energy: f64, // energy that has left the lattice or been dissipated by the continuous rules
+    pub alpha: f32, // fraction of a collapsing cell's energy each neighbor receives with the OFC rule
+    pub saturated: bool, // an avalanche could not end because no grains can leave the lattice
+    pub sources: Vec<(u8, u8)>, // drop cell that fed each cell most and its lead - empty unless sources are tracked
+    pub changed: Vec<(usize, usize)>, // first and last column of each row changed since take_changed() - empty if first > last
+    visits: Vec<u32>, // avalanche in which each cell last collapsed - empty until the first avalanche
+    avalanches: u32,  // number of the current avalanche in visits
+}
+impl Default for Model {
+    fn default() -> Self {
+        Self::new(MODEL_WIDTH, MODEL_HEIGHT)
+    }
 }
 impl Model {
-    pub fn default() -> Self {
-        let size = MODEL_WIDTH
-            .checked_mul(MODEL_HEIGHT)
-            .expect("Table too big");
+    /// new() creates an empty lattice of width by height cells
+    pub fn new(width: usize, height: usize) -> Self {
+        let size = width.checked_mul(height).expect("Table too big");
         let drop_cells: [usize; MAX_DROPS] = [0; MAX_DROPS];
         Self {
             cells: vec![Cell::default(); size],
-            width: MODEL_WIDTH,
-            height: MODEL_HEIGHT,
+            width,
+            height,
             total_grains: 0,
             lost_grains: 0,
             drop_cells,
             ac: 0,
+            schedules: [Schedule::default(); MAX_DROPS],
+            turn: 0,
             active_cells: 0,
             hues: Hues::default(),
             interval: 1_024,
-            avalanche: 0,
+            avalanche: Avalanche::default(),
+            histogram: Histogram::default(),
+            growth: Vec::new(),
+            critical: CRITICAL,
+            boundary_x: Boundary::Open,
+            boundary_y: Boundary::Open,
+            lattice: Lattice::Square,
+            rule: Rule::Btw,
+            spread: 0,
+            dissipation: 0.0,
+            seed: SEED,
+            rng: Rng::new(SEED),
+            thresholds: Vec::new(),
+            energy: Vec::new(),
+            lost_energy: 0.0,
+            alpha: ALPHA,
+            saturated: false,
+            sources: Vec::new(),
+            changed: vec![(0, width.saturating_sub(1)); height],
+            visits: Vec::new(),
+            avalanches: 0,
         }
     }
     /// calc_center_idx() returns the index of the center cell
     pub fn calc_center_idx(&self) -> usize {
         let size = self.width.checked_mul(self.height).expect("Table too big");
         let center_idx: usize = {
-            if self.height % 2 == 0 {
-                if self.width % 2 == 0 {
+            if self.height.is_multiple_of(2) {
+                if self.width.is_multiple_of(2) {
                     (size / 2) + (self.width / 2)
                 } else {
                     (size / 2) + ((self.width - 1) / 2)
@@ -125,132 +403,433 @@ impl Model {
         let x = idx % self.width;
         (x, y)
     }
-    /// add_grain() drops one grain of sand on the designated cell and checks if it collapsed
-    pub fn add_grain(&mut self) {
+    /// reseed() restarts rng from seed and draws new random thresholds if spread > 0
+    pub fn reseed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.rng = Rng::new(seed);
+        // thresholds never exceed MAX_CRITICAL so every stable cell has a hue
+        let highest = self.critical.saturating_add(self.spread).min(MAX_CRITICAL);
+        self.thresholds = match self.spread {
+            0 => Vec::new(),
+            _ => (0..self.width * self.height)
+                .map(|_| {
+                    self.critical + self.rng.below((highest - self.critical) as usize + 1) as u8
+                })
+                .collect(),
+        };
+    }
+    /// threshold() returns the number of grains that collapses cell idx
+    pub fn threshold(&self, idx: usize) -> u8 {
+        match self.thresholds.get(idx) {
+            Some(&threshold) => threshold,
+            None => self.critical,
+        }
+    }
+    /// energy_hue() returns the color of cell idx for the continuous rules - the palette spans zero to the threshold
+    pub fn energy_hue(&self, idx: usize) -> Color {
+        let fraction = self.energy[idx] / self.threshold(idx) as f32;
+        self.hues.energy_hue(fraction * (self.critical - 1) as f32)
+    }
+    /// take_changed() returns the columns of each row changed since it was last called and starts afresh
+    pub fn take_changed(&mut self) -> Vec<(usize, usize)> {
+        std::mem::replace(&mut self.changed, vec![(usize::MAX, 0); self.height])
+    }
+    // touch() records that cell (x, y) and its neighbors have changed - a collapse only reaches cells one row and
+    // one column away, or the far side of a periodic edge
+    fn touch(&mut self, x: usize, y: usize) {
+        let (left, right) = (x.saturating_sub(1), (x + 1).min(self.width - 1));
+        let (top, bottom) = (y.saturating_sub(1), (y + 1).min(self.height - 1));
+        let wraps_x = self.boundary_x == Boundary::Periodic && (x == 0 || x == self.width - 1);
+        let wraps_y = self.boundary_y == Boundary::Periodic && (y == 0 || y == self.height - 1);
+        let edges = wraps_y.then_some([0, self.height - 1]);
+        let rows = (top..=bottom).chain(edges.into_iter().flatten());
+        for row in rows {
+            if let Some(span) = self.changed.get_mut(row) {
+                match wraps_x {
+                    true => *span = (0, self.width - 1),
+                    false => *span = (span.0.min(left), span.1.max(right)),
+                }
+            }
+        }
+    }
+    /// cell_hue() returns the color cell idx is drawn in - tinted by the drop cell that fed it if sources are tracked
+    pub fn cell_hue(&self, idx: usize) -> Color {
+        let hue = match self.cells[idx].grains {
+            _ if self.rule.is_continuous() && self.cells[idx].borged => self.energy_hue(idx),
+            0 => match self.cells[idx].borged {
+                true => self.hues.grains[0],
+                false => self.hues.untouched,
+            },
+            grains => self.hues.grain_hue(grains),
+        };
+        match self.sources.get(idx) {
+            Some(&(source, _)) if source != UNFED => Hues::tint(hue, source as usize),
+            _ => hue,
+        }
+    }
+    /// track_sources() starts or stops recording which drop cell fed each cell most - grains passed on by a collapsing
+    /// cell count for the drop cell that fed it most, and cells fed before tracking started have no source
+    pub fn track_sources(&mut self, track: bool) {
+        self.sources = match track {
+            true => vec![(UNFED, 0); self.cells.len()],
+            false => Vec::new(),
+        };
+        // every cell changes color
+        self.changed = vec![(0, self.width.saturating_sub(1)); self.height];
+    }
+    // vote() counts a grain that reached cell idx - a Boyer-Moore majority vote that keeps one candidate per cell
+    // and its lead over every other drop cell
+    // a dropped grain comes from the current drop cell and a grain sent by cell from comes from whichever drop cell
+    // fed that cell most
+    fn vote(&mut self, idx: usize, from: Option<usize>) {
+        if self.sources.is_empty() {
+            return;
+        }
+        let voter = match from {
+            Some(from) => self.sources[from].0,
+            None => self.ac as u8,
+        };
+        let (source, lead) = &mut self.sources[idx];
+        if voter == UNFED {
+            // a cell fed before tracking started doesn't know where its grains came from
+        } else if *source == voter {
+            *lead = lead.saturating_add(1);
+        } else if *lead == 0 {
+            (*source, *lead) = (voter, 1);
+        } else {
+            *lead -= 1;
+        }
+    }
+    /// add_grain() drops one grain of sand on the designated cell, resolves any collapse, and records the avalanche
+    pub fn add_grain(&mut self) -> Avalanche {
+        let idx = self.drop_cells[self.ac];
+        let (x, y) = self.idx_to_xy(idx);
+        if self.saturated {
+            // the lattice can't hold another grain
+            return Avalanche::start(x, y);
+        }
         self.total_grains += 1;
-        self.avalanche = 0;
-        self.cells[self.drop_cells[self.ac]].grains += 1;
-        self.cells[self.drop_cells[self.ac]].borged = true;
-        if self.cells[self.drop_cells[self.ac]].grains >= CRITICAL {
-            self.unstable(self.drop_cells[self.ac]);
-        }
-    }
-    // a previous version of unstable() recursively called itself and evaluated adj cells internally
-    // this resulted in a stack overflow around 5M sand grains
-    // a second version added a fn for each direction that then called unstable()
-    // the second version resulted in a stack overflow around 10M sand grains
-    // this version evaluates the second iteration in each adjacent cell and recursively calls the adjacent functions
-    // this third version seems to overflow at ~20M grains - convert to iterative function?
-    /// unstable() evaluates cell collapse for drop cell and calls a similar fn for each adjacent cell
-    fn unstable(&mut self, idx: usize) {
-        self.avalanche += 1;
-        self.cells[idx].grains -= CRITICAL;
-        self.minusy(idx);
-        self.plusy(idx);
-        self.minusx(idx);
-        self.plusx(idx);
-    }
-    /// minusy() evaluates the cell above
-    fn minusy(&mut self, idx: usize) {
-        if idx as i32 - (self.width as i32) < 0 {
-            self.lost_grains += 1;
+        self.avalanche = Avalanche::start(x, y);
+        self.borg(idx);
+        self.touch(x, y);
+        self.vote(idx, None);
+        if self.rule.is_continuous() {
+            // continuous rules are driven by a random amount of energy - half a grain on average
+            if self.energy.len() != self.cells.len() {
+                self.energy = vec![0.0; self.cells.len()];
+            }
+            self.energy[idx] += self.rng.unit() as f32;
+            if self.energy[idx] >= self.threshold(idx) as f32 {
+                self.unstable(idx);
+            }
         } else {
-            let nidx = idx - self.width;
-            self.cells[nidx].grains += 1;
-            self.cells[nidx].borged = true;
-            if self.cells[nidx].grains == CRITICAL {
-                self.avalanche += 1;
-                self.cells[nidx].grains -= CRITICAL;
-                self.minusy(nidx);
-                self.plusy(nidx);
-                self.minusx(nidx);
-                self.plusx(nidx);
+            self.cells[idx].grains += 1;
+            if self.cells[idx].grains >= self.threshold(idx) {
+                self.unstable(idx);
             }
         }
+        self.histogram.record(&self.avalanche);
+        if self.growth.len() <= self.ac {
+            self.growth.resize(self.ac + 1, Growth::default());
+        }
+        self.growth[self.ac].record(&self.avalanche);
+        self.avalanche
     }
-    /// plusy() evaluates the cell below
-    fn plusy(&mut self, idx: usize) {
-        if idx + self.width >= self.width * self.height {
-            self.lost_grains += 1;
-        } else {
-            let nidx = idx + self.width;
-            self.cells[nidx].grains += 1;
-            self.cells[nidx].borged = true;
-            if self.cells[nidx].grains == CRITICAL {
-                self.avalanche += 1;
-                self.cells[nidx].grains -= CRITICAL;
-                self.minusy(nidx);
-                self.plusy(nidx);
-                self.minusx(nidx);
-                self.plusx(nidx);
+    // borg() marks cell idx as touched - the avalanche counts the cells touched for the first time
+    fn borg(&mut self, idx: usize) {
+        if !self.cells[idx].borged {
+            self.cells[idx].borged = true;
+            self.avalanche.touched += 1;
+        }
+    }
+    /// drop_grain() adds a grain to the current drop cell - each cell that is dropping takes its turn, rate grains at a time
+    pub fn drop_grain(&mut self) -> Avalanche {
+        if !self.is_dropping(self.ac) || self.turn >= self.schedules[self.ac].rate {
+            self.turn = 0;
+            for _ in 0..self.active_cells {
+                self.ac = (self.ac + 1) % self.active_cells;
+                if self.is_dropping(self.ac) {
+                    break;
+                }
             }
         }
+        if !self.is_dropping(self.ac) {
+            // every drop cell is waiting to start or has stopped
+            return Avalanche::default();
+        }
+        self.turn += 1;
+        self.add_grain()
     }
-    /// minusx() evaluates the cell to the left
-    fn minusx(&mut self, idx: usize) {
-        if idx % self.width == 0 {
-            self.lost_grains += 1;
-        } else {
-            let nidx = idx - 1;
-            self.cells[nidx].grains += 1;
-            self.cells[nidx].borged = true;
-            if self.cells[nidx].grains == CRITICAL {
-                self.avalanche += 1;
-                self.cells[nidx].grains -= CRITICAL;
-                self.minusy(nidx);
-                self.plusy(nidx);
-                self.minusx(nidx);
-                self.plusx(nidx);
+    /// is_dropping() is true if drop cell i is active, isn't paused, and its schedule covers the current grain count
+    pub fn is_dropping(&self, i: usize) -> bool {
+        let schedule = self.schedules[i];
+        i < self.active_cells
+            && schedule.paused.is_none()
+            && schedule.start <= self.total_grains
+            && schedule.stop.is_none_or(|stop| self.total_grains < stop)
+    }
+    /// is_idle() is true if no drop cell is dropping - the grain count can't change until one is added
+    pub fn is_idle(&self) -> bool {
+        !(0..self.active_cells).any(|i| self.is_dropping(i))
+    }
+    /// remove_drop_cell() stops drop cell i for good - the drop cells after it move down one place and the next grain
+    /// goes to the cell that followed it
+    pub fn remove_drop_cell(&mut self, i: usize) {
+        if i >= self.active_cells {
+            return;
+        }
+        self.drop_cells[i..].rotate_left(1);
+        self.schedules[i..].rotate_left(1);
+        self.drop_cells[MAX_DROPS - 1] = 0;
+        self.schedules[MAX_DROPS - 1] = Schedule::default();
+        self.active_cells -= 1;
+        if i < self.growth.len() {
+            self.growth.remove(i);
+        }
+        // cells remember the drop cell that fed them as the ones after i move down - their tint changes with it
+        for (idx, (source, lead)) in self
+            .sources
+            .iter_mut()
+            .enumerate()
+            .filter(|(_, (source, _))| *source != UNFED)
+        {
+            match (*source as usize).cmp(&i) {
+                std::cmp::Ordering::Equal => (*source, *lead) = (UNFED, 0),
+                std::cmp::Ordering::Greater => *source -= 1,
+                std::cmp::Ordering::Less => continue,
             }
+            let (x, span) = (idx % self.width, &mut self.changed[idx / self.width]);
+            *span = (span.0.min(x), span.1.max(x));
+        }
+        if self.ac == i {
+            // drop_grain() moves on from the cell before - its turn is over
+            self.ac = (i + self.active_cells.max(1) - 1) % self.active_cells.max(1);
+            self.turn = u32::MAX;
+        } else if self.ac > i {
+            self.ac -= 1;
         }
     }
-    // plusx() evaluates the cell to the right
-    fn plusx(&mut self, idx: usize) {
-        if (idx + 1) % self.width == 0 {
-            self.lost_grains += 1;
-        } else {
-            let nidx = idx + 1;
-            self.cells[nidx].grains += 1;
-            self.cells[nidx].borged = true;
-            if self.cells[nidx].grains == CRITICAL {
-                self.avalanche += 1;
-                self.cells[nidx].grains -= CRITICAL;
-                self.minusy(nidx);
-                self.plusy(nidx);
-                self.minusx(nidx);
-                self.plusx(nidx);
+    /// move_drop_cell() moves drop cell i to cell - its growth starts again from the new cell
+    pub fn move_drop_cell(&mut self, i: usize, cell: usize) {
+        if i >= self.active_cells || cell >= self.cells.len() {
+            return;
+        }
+        self.drop_cells[i] = cell;
+        if i < self.growth.len() {
+            self.growth[i] = Growth::default();
+        }
+    }
+    // previous versions of unstable() resolved collapses recursively - either by calling itself or by calling
+    // a fn for each direction (minusy(), plusy(), minusx(), plusx()) - and overflowed the stack somewhere
+    // between 5M and 20M sand grains
+    // this version keeps the cells waiting to collapse in heap allocated queues so an avalanche can be any size
+    // each queue holds one generation - every unstable cell collapses once before the next generation starts
+    // the abelian property of the sandpile model guarantees the same lattice regardless of collapse order
+    // a collapsing cell sends threshold / neighbors grains to each neighbor - any remainder is dissipated and counted as lost
+    // the Manna rule sends the same number of grains one at a time to randomly chosen neighbors instead, and
+    // with dissipation each grain may be lost on its way - neither is abelian so the order of collapses matters
+    // the continuous rules empty a collapsing cell and move its energy the same way - see release()
+    // without open edges or dissipation an avalanche may never end - it is certain not to once every cell has
+    // collapsed (Bjorner, Lovasz & Shor 1991) so the avalanche is stopped there and the model is marked saturated
+    /// unstable() collapses the drop cell and every cell that becomes critical as a result
+    fn unstable(&mut self, idx: usize) {
+        let conservative = self.is_conservative();
+        let lost_energy = self.lost_energy;
+        let (drop_x, drop_y) = (idx % self.width, idx / self.width);
+        let mut furthest: usize = 0; // squared distance from the drop cell
+                                     // a cell adds to the area the first time it collapses - its visit is stamped with this avalanche's number
+        if self.visits.len() != self.cells.len() || self.avalanches == u32::MAX {
+            self.visits = vec![0; self.cells.len()];
+            self.avalanches = 0;
+        }
+        self.avalanches += 1;
+        let mut area: usize = 0;
+        let mut generation: Vec<usize> = vec![idx];
+        let mut next: Vec<usize> = Vec::new();
+        while !generation.is_empty() {
+            if conservative && area == self.cells.len() {
+                self.saturated = true;
+                break;
+            }
+            self.avalanche.duration += 1;
+            for &idx in generation.iter() {
+                self.avalanche.topples += 1;
+                if self.visits[idx] != self.avalanches {
+                    self.visits[idx] = self.avalanches;
+                    area += 1;
+                    let (x, y) = (idx % self.width, idx / self.width);
+                    self.avalanche.min_x = self.avalanche.min_x.min(x);
+                    self.avalanche.max_x = self.avalanche.max_x.max(x);
+                    self.avalanche.min_y = self.avalanche.min_y.min(y);
+                    self.avalanche.max_y = self.avalanche.max_y.max(y);
+                    furthest = furthest.max(x.abs_diff(drop_x).pow(2) + y.abs_diff(drop_y).pow(2));
+                    // every cell that receives grains or energy is next to one that collapsed
+                    self.touch(x, y);
+                }
+                if self.rule.is_continuous() {
+                    self.release(idx, &mut next);
+                } else {
+                    self.topple(idx, &mut next);
+                }
+            }
+            std::mem::swap(&mut generation, &mut next);
+            next.clear();
+        }
+        self.avalanche.area = area;
+        self.avalanche.radius = (furthest as f32).sqrt();
+        if self.rule.is_continuous() {
+            // lost energy is reported in grains
+            self.avalanche.lost = (self.lost_energy - lost_energy).round() as usize;
+        }
+    }
+    /// topple() collapses cell idx by sending its grains to its neighbors
+    fn topple(&mut self, idx: usize, next: &mut Vec<usize>) {
+        let critical = self.threshold(idx);
+        let (adjacent, count) = self.neighbors(idx);
+        let share = critical / count as u8;
+        let dissipated = (critical % count as u8) as usize;
+        self.cells[idx].grains -= critical;
+        self.lost_grains += dissipated;
+        self.avalanche.lost += dissipated;
+        match self.rule {
+            Rule::Manna => {
+                for _ in 0..share as usize * count {
+                    let neighbor = adjacent[self.rng.below(count)];
+                    self.deliver(idx, neighbor, 1, next);
+                }
+            }
+            _ => {
+                for &neighbor in adjacent[..count].iter() {
+                    self.deliver(idx, neighbor, share, next);
+                }
+            }
+        }
+        // a cell that received grains while waiting may still be unstable
+        if self.cells[idx].grains >= critical {
+            next.push(idx);
+        }
+    }
+    /// release() collapses cell idx by emptying its energy into its neighbors
+    fn release(&mut self, idx: usize, next: &mut Vec<usize>) {
+        let (adjacent, count) = self.neighbors(idx);
+        let energy = std::mem::take(&mut self.energy[idx]);
+        let transfer = match self.rule {
+            Rule::Ofc => energy * self.alpha,
+            _ => energy / count as f32,
+        };
+        // whatever the neighbors don't receive is dissipated
+        self.lost_energy += (energy - transfer * count as f32).max(0.0) as f64;
+        for &neighbor in adjacent[..count].iter() {
+            if self.dissipation > 0.0 && self.rng.chance(self.dissipation) {
+                self.lost_energy += transfer as f64;
+                continue;
+            }
+            match neighbor {
+                _ if transfer <= 0.0 => (),
+                None => self.lost_energy += transfer as f64,
+                Some(nidx) => {
+                    let critical = self.threshold(nidx) as f32;
+                    let before = self.energy[nidx];
+                    self.energy[nidx] += transfer;
+                    self.borg(nidx);
+                    self.vote(nidx, Some(idx));
+                    if nidx != idx && before < critical && self.energy[nidx] >= critical {
+                        next.push(nidx);
+                    }
+                }
+            }
+        }
+        if self.energy[idx] >= self.threshold(idx) as f32 {
+            next.push(idx);
+        }
+    }
+    /// deliver() sends grains from collapsing cell idx to a neighbor and queues the neighbor if it becomes critical
+    fn deliver(&mut self, idx: usize, neighbor: Option<usize>, grains: u8, next: &mut Vec<usize>) {
+        let mut grains = grains;
+        if self.dissipation > 0.0 {
+            let kept = (0..grains)
+                .filter(|_| !self.rng.chance(self.dissipation))
+                .count() as u8;
+            self.lost_grains += (grains - kept) as usize;
+            self.avalanche.lost += (grains - kept) as usize;
+            grains = kept;
+        }
+        match neighbor {
+            _ if grains == 0 => (),
+            None => {
+                self.lost_grains += grains as usize;
+                self.avalanche.lost += grains as usize;
+            }
+            Some(nidx) => {
+                let critical = self.threshold(nidx);
+                let before = self.cells[nidx].grains;
+                self.cells[nidx].grains += grains;
+                self.borg(nidx);
+                self.vote(nidx, Some(idx));
+                // only queue a cell once - when it first reaches critical
+                // grains returned to the collapsing cell by a closed edge are checked by unstable()
+                if nidx != idx && before < critical && self.cells[nidx].grains >= critical {
+                    next.push(nidx);
+                }
+            }
+        }
+    }
+    /// is_conservative() is true when grains can neither fall off the lattice nor be dissipated
+    // a collapse dissipates the remainder of its threshold, so every threshold a cell may draw has to divide evenly
+    pub fn is_conservative(&self) -> bool {
+        self.boundary_x != Boundary::Open
+            && self.boundary_y != Boundary::Open
+            && self.dissipation <= 0.0
+            && match self.rule {
+                Rule::Btw | Rule::Manna => {
+                    let highest = self.critical.saturating_add(self.spread);
+                    (self.critical..=highest)
+                        .all(|threshold| threshold.is_multiple_of(self.lattice.neighbors()))
+                }
+                Rule::Zhang => true,
+                Rule::Ofc => self.alpha * self.lattice.neighbors() as f32 >= 1.0,
+            }
+    }
+    /// neighbors() returns the index of each neighbor of idx and how many there are - None if off the 'table'
+    pub(crate) fn neighbors(&self, idx: usize) -> ([Option<usize>; 8], usize) {
+        let (x, y) = (idx % self.width, idx / self.width);
+        let offsets = self.lattice.offsets(x, y);
+        let mut adjacent: [Option<usize>; 8] = [None; 8];
+        for (neighbor, &(dx, dy)) in adjacent.iter_mut().zip(offsets.iter()) {
+            *neighbor = self.resolve(idx, x as isize + dx, y as isize + dy);
+        }
+        (adjacent, offsets.len())
+    }
+    /// resolve() finds the cell at (x, y), applying the boundary conditions if it is beyond an edge
+    fn resolve(&self, idx: usize, mut x: isize, mut y: isize) -> Option<usize> {
+        let (width, height) = (self.width as isize, self.height as isize);
+        if x < 0 || x >= width {
+            match self.boundary_x {
+                Boundary::Open => return None,
+                Boundary::Closed => return Some(idx), // the grain bounces back into the collapsing cell
+                Boundary::Periodic => x = x.rem_euclid(width),
+            }
+        }
+        if y < 0 || y >= height {
+            match self.boundary_y {
+                Boundary::Open => return None,
+                Boundary::Closed => return Some(idx),
+                Boundary::Periodic => y = y.rem_euclid(height),
             }
         }
+        Some(self.xy_to_idx(x as usize, y as usize))
     }
-    /// random_colors() generates random RGBA values for 'Hues' using macroquads quad_rand crate
+    /// random_colors() generates random RGBA values for 'Hues' - seeded from the clock so it doesn't disturb rng
     pub fn random_colors(&mut self) {
-        srand(self.total_grains as u64 + (get_time() * 256.0).trunc() as u64);
+        let nanos = match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(elapsed) => elapsed.as_nanos() as u64,
+            Err(_) => 0,
+        };
+        let mut rng = Rng::new(self.total_grains as u64 ^ nanos);
         self.hues.untouched = Color::new(0.00, 0.00, 0.00, 0.00);
-        self.hues.zero_grains = Color::new(
-            gen_range::<f32>(0.0, 1.0),
-            gen_range::<f32>(0.0, 1.0),
-            gen_range::<f32>(0.0, 1.0),
-            1.0,
-        );
-        self.hues.one_grain = Color::new(
-            gen_range::<f32>(0.0, 1.0),
-            gen_range::<f32>(0.0, 1.0),
-            gen_range::<f32>(0.0, 1.0),
-            1.0,
-        );
-        self.hues.two_grains = Color::new(
-            gen_range::<f32>(0.0, 1.0),
-            gen_range::<f32>(0.0, 1.0),
-            gen_range::<f32>(0.0, 1.0),
-            1.0,
-        );
-        self.hues.three_grains = Color::new(
-            gen_range::<f32>(0.0, 1.0),
-            gen_range::<f32>(0.0, 1.0),
-            gen_range::<f32>(0.0, 1.0),
-            1.0,
-        );
-        self.hues.four_grains = Color::new(1.00, 0.00, 0.00, 1.00);
+        for hue in self.hues.grains.iter_mut() {
+            *hue = Color::new(rng.unit() as f32, rng.unit() as f32, rng.unit() as f32, 1.0);
+        }
     }
     /// find_extent() returns the minimum x, minimum y, width, and height of the active area of the model
     pub fn find_extent(&self) -> (u32, u32, u16, u16) {
@@ -279,20 +858,20 @@ impl Model {
         } else {
             min_x = 0
         };
-        if max_x <= MODEL_WIDTH - 10 {
+        if max_x + 10 <= self.width {
             max_x += 10;
         } else {
-            max_x = MODEL_WIDTH;
+            max_x = self.width;
         };
         if min_y >= 10 {
             min_y -= 10;
         } else {
             min_y = 0;
         };
-        if max_y <= MODEL_HEIGHT - 10 {
+        if max_y + 10 <= self.height {
             max_y += 10;
         } else {
-            max_y = MODEL_HEIGHT;
+            max_y = self.height;
         };
         (
             min_x.try_into().expect("Too big"),
@@ -301,262 +880,47 @@ impl Model {
             (max_y - min_y).try_into().expect("Too big"),
         )
     }
-    /// paint() exports a PNG image of the current model using the macroquad export_png() function
-    pub fn paint(&self, tlx: u32, tly: u32, x_width: u16, y_height: u16) {
-        let mut sand_painting = Image::gen_image_color(x_width, y_height, BLANK);
-        for row in 0..y_height as usize - 1 {
-            for column in 0..x_width as usize - 1 {
-                let idx = self.xy_to_idx(column + tlx as usize, row + tly as usize);
-                match self.cells[idx].grains {
-                    0 => {
-                        if !self.cells[idx].borged {
-                            sand_painting.set_pixel(column as u32, row as u32, self.hues.untouched);
-                        } else {
-                            sand_painting.set_pixel(
-                                column as u32,
-                                row as u32,
-                                self.hues.zero_grains,
-                            );
-                        }
-                    }
-                    1 => sand_painting.set_pixel(column as u32, row as u32, self.hues.one_grain),
-                    2 => sand_painting.set_pixel(column as u32, row as u32, self.hues.two_grains),
-                    3 => sand_painting.set_pixel(column as u32, row as u32, self.hues.three_grains),
-                    _ => sand_painting.set_pixel(column as u32, row as u32, self.hues.four_grains),
+    /// render() draws the cells of a region of the model into an RGBA raster
+    pub fn render(&self, tlx: u32, tly: u32, x_width: u16, y_height: u16) -> Raster {
+        // hexagonal cells are two pixels wide so odd rows can be shifted by half a cell
+        let scale: usize = match self.lattice {
+            Lattice::Hexagonal => 2,
+            _ => 1,
+        };
+        let image_width = x_width as usize * scale + scale - 1;
+        let mut sand_painting = Raster::new(image_width, y_height as usize, BLANK);
+        // pixels beyond the edge of the lattice are left blank
+        let columns = (x_width as usize).min(self.width.saturating_sub(tlx as usize));
+        let rows = (y_height as usize).min(self.height.saturating_sub(tly as usize));
+        for row in 0..rows {
+            let shift = if (row + tly as usize).is_multiple_of(2) {
+                0
+            } else {
+                scale - 1
+            };
+            for column in 0..columns {
+                let pixel_color =
+                    self.cell_hue(self.xy_to_idx(column + tlx as usize, row + tly as usize));
+                for pixel in 0..scale {
+                    sand_painting.set_pixel(column * scale + shift + pixel, row, pixel_color);
                 }
             }
         }
+        sand_painting
+    }
+    /// paint() exports a PNG image of the current model using the macroquad export_png() function
+    #[cfg(feature = "gui")]
+    pub fn paint(&self, tlx: u32, tly: u32, x_width: u16, y_height: u16) {
+        let raster = self.render(tlx, tly, x_width, y_height);
+        let sand_painting = Image {
+            bytes: raster.bytes,
+            width: raster.width as u16,
+            height: raster.height as u16,
+        };
         // format name & export as PNG
         let fname = format!("Lakhesis_{:08}.png", &self.total_grains);
         sand_painting.export_png(&fname);
     }
-    /// curate() saves the model in its current state
-    pub fn curate(&self) {
-        let filename = format!("lakhesis_model_{:08}.lak", &self.total_grains);
-        match File::create(filename) {
-            Err(why) => {
-                eprintln!("Error creating text file {}", why);
-                std::process::exit(1);
-            }
-            Ok(model_lines) => {
-                let mut model_lines = LineWriter::new(model_lines);
-                // usize fields
-                let mut entry = format!(
-                    "lakhesis,alpha,{},{},{},{},{},{},{}\ndrops,",
-                    &self.width,
-                    &self.height,
-                    &self.total_grains,
-                    &self.lost_grains,
-                    &self.interval,
-                    &self.active_cells,
-                    &self.avalanche
-                );
-                model_lines.write_all(entry.as_bytes()).unwrap();
-                // array of 32 usize
-                for i in 0..MAX_DROPS {
-                    if i == MAX_DROPS - 1 {
-                        entry = format!("{}\nhues,", &self.drop_cells[i]);
-                        model_lines.write_all(entry.as_bytes()).unwrap();
-                    } else {
-                        entry = format!("{},", &self.drop_cells[i]);
-                        model_lines.write_all(entry.as_bytes()).unwrap();
-                    }
-                }
-                // hues are six sets of four f32s
-                entry = format!(
-                    "{},{},{},{},",
-                    &self.hues.untouched.r,
-                    &self.hues.untouched.g,
-                    &self.hues.untouched.b,
-                    &self.hues.untouched.a
-                );
-                model_lines.write_all(entry.as_bytes()).unwrap();
-                entry = format!(
-                    "{},{},{},{},",
-                    &self.hues.zero_grains.r,
-                    &self.hues.zero_grains.g,
-                    &self.hues.zero_grains.b,
-                    &self.hues.zero_grains.a
-                );
-                model_lines.write_all(entry.as_bytes()).unwrap();
-                entry = format!(
-                    "{},{},{},{},",
-                    &self.hues.one_grain.r,
-                    &self.hues.one_grain.g,
-                    &self.hues.one_grain.b,
-                    &self.hues.one_grain.a
-                );
-                model_lines.write_all(entry.as_bytes()).unwrap();
-                entry = format!(
-                    "{},{},{},{},",
-                    &self.hues.two_grains.r,
-                    &self.hues.two_grains.g,
-                    &self.hues.two_grains.b,
-                    &self.hues.two_grains.a
-                );
-                model_lines.write_all(entry.as_bytes()).unwrap();
-                entry = format!(
-                    "{},{},{},{},",
-                    &self.hues.three_grains.r,
-                    &self.hues.three_grains.g,
-                    &self.hues.three_grains.b,
-                    &self.hues.three_grains.a
-                );
-                model_lines.write_all(entry.as_bytes()).unwrap();
-                entry = format!(
-                    "{},{},{},{}\n",
-                    &self.hues.four_grains.r,
-                    &self.hues.four_grains.g,
-                    &self.hues.four_grains.b,
-                    &self.hues.four_grains.a
-                );
-                model_lines.write_all(entry.as_bytes()).unwrap();
-                // cells - a bit more complicated
-                let mut cursor: usize = 0;
-                let mut eof: bool = false;
-                let mut subtotal: usize = 0;
-                while !eof {
-                    // never touched cells
-                    if !self.cells[cursor].borged {
-                        let mut alt_cursor: usize = cursor;
-                        while !self.cells[alt_cursor + 1].borged && !eof {
-                            if alt_cursor < self.cells.len() - 2 {
-                                alt_cursor += 1;
-                            } else {
-                                eof = true;
-                            };
-                        }
-                        let mut count: usize = alt_cursor - cursor + 1;
-                        if eof {
-                            count += 1;
-                        };
-                        subtotal += count;
-                        entry = format!("{},f\n", &count);
-                        model_lines.write_all(entry.as_bytes()).unwrap();
-                        cursor = alt_cursor + 1;
-                    } else {
-                        // cells that have contained grains
-                        let mut alt_cursor: usize = cursor;
-                        while self.cells[alt_cursor + 1].borged && !eof {
-                            if alt_cursor < self.cells.len() - 2 {
-                                alt_cursor += 1;
-                            } else {
-                                eof = true;
-                            };
-                        }
-                        let mut count: usize = alt_cursor - cursor + 1;
-                        if eof {
-                            count += 1;
-                        };
-                        subtotal += count;
-                        entry = format!("{},t,", &count);
-                        model_lines.write_all(entry.as_bytes()).unwrap();
-                        for i in cursor..cursor + count {
-                            entry = format!("{}", &self.cells[i].grains);
-                            model_lines.write_all(entry.as_bytes()).unwrap();
-                        }
-                        model_lines.write_all("\n".to_string().as_bytes()).unwrap();
-                        cursor = alt_cursor + 1;
-                    }
-                }
-                entry = format!(
-                    "Checksum: {} of {} cells recorded",
-                    &subtotal,
-                    &self.cells.len()
-                );
-                model_lines.write_all(entry.as_bytes()).unwrap();
-            }
-        }
-    }
-    /// uncurate() loads a model saved using curate() - currently filename must be "lakhesis.lak"
-    pub fn uncurate(&mut self, filename: String) {
-        let mut temp_cells: Vec<Cell> = Vec::new();
-        let source = File::open(&filename).expect("Unable to open file");
-        let reader = BufReader::new(source);
-        let lines = reader.lines();
-        for line in lines {
-            match line {
-                Err(_) => eprintln!("Error reading line: {:?}", line),
-                Ok(line) => {
-                    if line.contains("lakhesis") {
-                        let pieces: Vec<&str> = line.split(',').collect();
-                        self.width = pieces[2].parse::<usize>().unwrap();
-                        self.height = pieces[3].parse::<usize>().unwrap();
-                        self.total_grains = pieces[4].parse::<usize>().unwrap();
-                        self.lost_grains = pieces[5].parse::<usize>().unwrap();
-                        self.interval = pieces[6].parse::<usize>().unwrap();
-                        self.active_cells = pieces[7].parse::<usize>().unwrap();
-                        self.avalanche = pieces[8].parse::<usize>().unwrap();
-                    } else if line.contains("drops") {
-                        let pieces: Vec<&str> = line.split(',').collect();
-                        for i in 0..MAX_DROPS {
-                            self.drop_cells[i] = pieces[i + 1].parse::<usize>().unwrap();
-                        }
-                    } else if line.contains("hues") {
-                        let pieces: Vec<&str> = line.split(',').collect();
-                        self.hues.untouched.r = pieces[1].parse::<f32>().unwrap();
-                        self.hues.untouched.g = pieces[2].parse::<f32>().unwrap();
-                        self.hues.untouched.b = pieces[3].parse::<f32>().unwrap();
-                        self.hues.untouched.a = pieces[4].parse::<f32>().unwrap();
-                        self.hues.zero_grains.r = pieces[5].parse::<f32>().unwrap();
-                        self.hues.zero_grains.g = pieces[6].parse::<f32>().unwrap();
-                        self.hues.zero_grains.b = pieces[7].parse::<f32>().unwrap();
-                        self.hues.zero_grains.a = pieces[8].parse::<f32>().unwrap();
-                        self.hues.one_grain.r = pieces[9].parse::<f32>().unwrap();
-                        self.hues.one_grain.g = pieces[10].parse::<f32>().unwrap();
-                        self.hues.one_grain.b = pieces[11].parse::<f32>().unwrap();
-                        self.hues.one_grain.a = pieces[12].parse::<f32>().unwrap();
-                        self.hues.two_grains.r = pieces[13].parse::<f32>().unwrap();
-                        self.hues.two_grains.g = pieces[14].parse::<f32>().unwrap();
-                        self.hues.two_grains.b = pieces[15].parse::<f32>().unwrap();
-                        self.hues.two_grains.a = pieces[16].parse::<f32>().unwrap();
-                        self.hues.three_grains.r = pieces[17].parse::<f32>().unwrap();
-                        self.hues.three_grains.g = pieces[18].parse::<f32>().unwrap();
-                        self.hues.three_grains.b = pieces[19].parse::<f32>().unwrap();
-                        self.hues.three_grains.a = pieces[20].parse::<f32>().unwrap();
-                        self.hues.four_grains.r = pieces[21].parse::<f32>().unwrap();
-                        self.hues.four_grains.g = pieces[22].parse::<f32>().unwrap();
-                        self.hues.four_grains.b = pieces[23].parse::<f32>().unwrap();
-                        self.hues.four_grains.a = pieces[24].parse::<f32>().unwrap();
-                    } else if line.contains("Checksum:") {
-                        let pieces: Vec<&str> = line.split(' ').collect();
-                        if pieces[1] != pieces[3] {
-                            eprintln!(
-                                "Checksum error in lakhesis.lak: {} != {}",
-                                &pieces[1], &pieces[3]
-                            );
-                        }
-                    } else {
-                        let pieces: Vec<&str> = line.split(',').collect();
-                        if pieces[1] == "f" {
-                            for _ in 0..pieces[0].parse::<usize>().unwrap() {
-                                let temp: Cell = Cell {
-                                    grains: 0,
-                                    borged: false,
-                                };
-                                temp_cells.push(temp);
-                            }
-                        };
-                        if pieces[1] == "t" {
-                            let grains: Vec<char> = pieces[2].chars().collect();
-                            for grain in grains {
-                                let temp: Cell = Cell {
-                                    grains: grain
-                                        .to_digit(10)
-                                        .unwrap()
-                                        .try_into()
-                                        .expect("Too big"),
-                                    borged: true,
-                                };
-                                temp_cells.push(temp);
-                            }
-                        }
-                    }
-                }
-            }
-        }
-        self.cells = temp_cells;
-    }
 }
 
 #[cfg(test)]
@@ -565,9 +929,11 @@ mod tests {
 
     #[test]
     fn conversions() {
-        let mut model = Model::default();
-        model.width = 2_700;
-        model.height = 2_700;
+        let model = Model {
+            width: 2_700,
+            height: 2_700,
+            ..Model::default()
+        };
         let idx: usize = 540_001;
         let x: usize = 1;
         let y: usize = 200;
@@ -576,4 +942,478 @@ mod tests {
         assert_eq!(model.calc_center_idx(), 3_646_350);
         assert_eq!(model.calc_center_xy(), (1350, 1350));
     }
+
+    #[test]
+    fn extent_within_lattice() {
+        let mut model = Model::new(40, 30);
+        model.active_cells = 1;
+        model.drop_cells[0] = model.xy_to_idx(3, 26);
+        for _ in 0..200 {
+            model.add_grain();
+        }
+        let (min_x, min_y, width, height) = model.find_extent();
+        assert_eq!(min_x, 0);
+        assert!(min_x as usize + width as usize <= model.width);
+        assert_eq!(min_y as usize + height as usize, model.height);
+    }
+
+    #[test]
+    fn collapse_threshold() {
+        for critical in Lattice::Square.neighbors()..=MAX_CRITICAL {
+            let mut model = Model::new(31, 31);
+            model.critical = critical;
+            model.active_cells = 1;
+            model.drop_cells[0] = model.calc_center_idx();
+            for _ in 0..3_000 {
+                model.add_grain();
+            }
+            let on_table: usize = model.cells.iter().map(|cell| cell.grains as usize).sum();
+            assert_eq!(on_table + model.lost_grains, model.total_grains);
+            assert!(model.cells.iter().all(|cell| cell.grains < critical));
+        }
+    }
+
+    #[test]
+    fn avalanche_records() {
+        let mut model = Model::new(3, 3);
+        model.active_cells = 1;
+        model.drop_cells[0] = model.calc_center_idx();
+        for _ in 0..3 {
+            assert_eq!(model.add_grain().topples, 0);
+        }
+        let first = model.add_grain();
+        assert_eq!(
+            (first.topples, first.area, first.duration, first.lost),
+            (1, 1, 1, 0)
+        );
+        assert_eq!(
+            (first.min_x, first.min_y, first.max_x, first.max_y),
+            (1, 1, 1, 1)
+        );
+        // grow a larger pile and check the records add up
+        let mut model = Model::new(41, 41);
+        model.active_cells = 1;
+        model.drop_cells[0] = model.xy_to_idx(12, 20);
+        let mut lost: usize = 0;
+        for _ in 0..5_000 {
+            let avalanche = model.add_grain();
+            assert!(avalanche.area <= avalanche.topples);
+            assert!(
+                avalanche.topples == 0
+                    || avalanche.area
+                        <= (avalanche.max_x - avalanche.min_x + 1)
+                            * (avalanche.max_y - avalanche.min_y + 1)
+            );
+            assert!(avalanche.duration <= avalanche.topples);
+            assert!(
+                avalanche.radius
+                    <= (avalanche.max_x - avalanche.min_x + avalanche.max_y - avalanche.min_y)
+                        as f32
+            );
+            lost += avalanche.lost;
+        }
+        assert_eq!(lost, model.lost_grains);
+        assert_eq!(model.histogram.grains, model.total_grains);
+        assert!(model.histogram.avalanches() > 0);
+        assert!(model.histogram.lost.keys().any(|&grains| grains > 0));
+    }
+
+    #[test]
+    fn boundaries() {
+        let grow = |boundary_x: Boundary, boundary_y: Boundary, grains: usize| {
+            let mut model = Model::new(24, 20);
+            model.boundary_x = boundary_x;
+            model.boundary_y = boundary_y;
+            model.active_cells = 1;
+            model.drop_cells[0] = model.xy_to_idx(2, 10);
+            for _ in 0..grains {
+                model.add_grain();
+            }
+            let on_table: usize = model.cells.iter().map(|cell| cell.grains as usize).sum();
+            assert_eq!(on_table + model.lost_grains, model.total_grains);
+            model
+        };
+        // closed and periodic edges keep every grain
+        let closed = grow(Boundary::Closed, Boundary::Closed, 1_000);
+        assert_eq!(closed.lost_grains, 0);
+        let periodic = grow(Boundary::Periodic, Boundary::Periodic, 1_000);
+        assert_eq!(periodic.lost_grains, 0);
+        // a pile near the left edge wraps around to the right edge
+        assert!(periodic.cells[periodic.xy_to_idx(23, 10)].borged);
+        // mixed edges only lose grains at the top and bottom
+        let mixed = grow(Boundary::Periodic, Boundary::Open, 1_000);
+        assert!(mixed.lost_grains > 0);
+        assert!(mixed.cells[mixed.xy_to_idx(23, 10)].borged);
+        // a lattice without an exit fills up instead of running forever
+        for boundary in [Boundary::Closed, Boundary::Periodic] {
+            let full = grow(boundary, boundary, 3 * 24 * 20 + 10);
+            assert!(full.saturated);
+            assert!(full.total_grains <= 3 * 24 * 20 + 1);
+        }
+        assert_eq!("periodic".parse::<Boundary>(), Ok(Boundary::Periodic));
+        assert_eq!(Boundary::Closed.to_string(), "closed");
+    }
+
+    #[test]
+    fn lattices() {
+        for lattice in [
+            Lattice::Square,
+            Lattice::Moore,
+            Lattice::Hexagonal,
+            Lattice::Triangular,
+        ] {
+            // neighbors are mutual on a periodic lattice with even dimensions
+            let mut model = Model::new(12, 10);
+            model.lattice = lattice;
+            model.boundary_x = Boundary::Periodic;
+            model.boundary_y = Boundary::Periodic;
+            for idx in 0..model.cells.len() {
+                let (adjacent, count) = model.neighbors(idx);
+                assert_eq!(count, lattice.neighbors() as usize);
+                for nidx in adjacent[..count].iter().map(|neighbor| neighbor.unwrap()) {
+                    let (back, back_count) = model.neighbors(nidx);
+                    assert!(
+                        back[..back_count].contains(&Some(idx)),
+                        "{} {}",
+                        lattice,
+                        idx
+                    );
+                }
+            }
+            // grains are conserved or lost at the open edges
+            let mut model = Model::new(30, 24);
+            model.lattice = lattice;
+            model.critical = lattice.neighbors();
+            model.active_cells = 1;
+            model.drop_cells[0] = model.calc_center_idx();
+            for _ in 0..4_000 {
+                model.add_grain();
+            }
+            let on_table: usize = model.cells.iter().map(|cell| cell.grains as usize).sum();
+            assert_eq!(on_table + model.lost_grains, model.total_grains);
+            assert!(model.cells.iter().all(|cell| cell.grains < model.critical));
+            assert_eq!(lattice.to_string().parse::<Lattice>(), Ok(lattice));
+        }
+        assert_eq!(Lattice::Triangular.neighbors(), 3);
+    }
+
+    #[test]
+    fn rules() {
+        let grow = |rule: Rule, spread: u8, dissipation: f32, seed: u64| {
+            let mut model = Model::new(32, 32);
+            model.rule = rule;
+            model.spread = spread;
+            model.dissipation = dissipation;
+            model.reseed(seed);
+            model.active_cells = 2;
+            model.drop_cells[0] = model.xy_to_idx(10, 16);
+            model.drop_cells[1] = model.xy_to_idx(21, 16);
+            for _ in 0..3_000 {
+                model.add_grain();
+                model.ac = (model.ac + 1) % model.active_cells;
+            }
+            let on_table: usize = model.cells.iter().map(|cell| cell.grains as usize).sum();
+            assert_eq!(on_table + model.lost_grains, model.total_grains);
+            assert!(
+                (0..model.cells.len()).all(|idx| model.cells[idx].grains < model.threshold(idx))
+            );
+            model
+        };
+        // the same seed grows the same stochastic pile
+        let manna = grow(Rule::Manna, 0, 0.0, 5);
+        let again = grow(Rule::Manna, 0, 0.0, 5);
+        let other = grow(Rule::Manna, 0, 0.0, 6);
+        let grains = |model: &Model| {
+            model
+                .cells
+                .iter()
+                .map(|cell| cell.grains)
+                .collect::<Vec<u8>>()
+        };
+        assert_eq!(grains(&manna), grains(&again));
+        assert_ne!(grains(&manna), grains(&other));
+        assert_ne!(grains(&manna), grains(&grow(Rule::Btw, 0, 0.0, 5)));
+        // random thresholds stay within critical..=critical + spread
+        let random = grow(Rule::Btw, 3, 0.0, 9);
+        assert_eq!(random.thresholds.len(), random.cells.len());
+        assert!(random
+            .thresholds
+            .iter()
+            .all(|&threshold| (4..=7).contains(&threshold)));
+        assert!(random.thresholds.iter().any(|&threshold| threshold != 4));
+        // every grain is lost when dissipation is certain - only the drop cells are touched
+        let lossy = grow(Rule::Btw, 0, 1.0, 1);
+        assert_eq!(lossy.cells.iter().filter(|cell| cell.borged).count(), 2);
+        assert!(!lossy.is_conservative());
+        // so is the remainder of a random threshold that doesn't divide among the neighbors
+        let mut closed = Model::new(8, 8);
+        closed.boundary_x = Boundary::Closed;
+        closed.boundary_y = Boundary::Closed;
+        assert!(closed.is_conservative());
+        closed.spread = 3;
+        assert!(!closed.is_conservative());
+        assert_eq!("manna".parse::<Rule>(), Ok(Rule::Manna));
+    }
+
+    #[test]
+    fn schedules() {
+        let mut model = Model::new(40, 20);
+        model.active_cells = 3;
+        for (i, x) in [5, 20, 35].into_iter().enumerate() {
+            model.drop_cells[i] = model.xy_to_idx(x, 10);
+        }
+        model.schedules[0].rate = 3;
+        model.schedules[1] = Schedule {
+            rate: 1,
+            start: 4,
+            stop: Some(9),
+            ..Schedule::default()
+        };
+        model.schedules[2].start = 12;
+        let mut order: Vec<usize> = Vec::new();
+        for _ in 0..16 {
+            model.drop_grain();
+            order.push(model.ac);
+        }
+        // the first cell drops three grains a turn - the second takes turns from 4 grains to 9, the third from 12
+        assert_eq!(order, [0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 2, 0, 0]);
+        // the default schedule is plain round robin
+        let mut plain = Model::new(40, 20);
+        plain.active_cells = 2;
+        plain.drop_cells[1] = 7;
+        plain.drop_grain();
+        assert_eq!(plain.ac, 0);
+        plain.drop_grain();
+        assert_eq!(plain.ac, 1);
+        // a model whose cells have all stopped is idle
+        let mut stopped = Model::new(10, 10);
+        stopped.active_cells = 1;
+        stopped.schedules[0].stop = Some(2);
+        for _ in 0..5 {
+            stopped.drop_grain();
+        }
+        assert!(stopped.is_idle());
+        assert_eq!(stopped.total_grains, 2);
+        // schedules are saved with the model
+        let filename = std::env::temp_dir()
+            .join(format!("lakhesis_schedules_{}.lak", std::process::id()))
+            .display()
+            .to_string();
+        model.curate_as(&filename).unwrap();
+        let mut loaded = Model::default();
+        loaded.uncurate(&filename).unwrap();
+        std::fs::remove_file(&filename).unwrap();
+        assert_eq!(loaded.schedules, model.schedules);
+        assert_eq!(loaded.total_grains, 16);
+    }
+
+    #[test]
+    fn continuous_rules() {
+        // the drive is the only random draw so the energy added can be replayed
+        let mut rng = Rng::new(3);
+        let added: f64 = (0..6_000).map(|_| rng.unit() as f32 as f64).sum();
+        let grow = |rule: Rule, alpha: f32| {
+            let mut model = Model::new(32, 32);
+            model.rule = rule;
+            model.alpha = alpha;
+            model.reseed(3);
+            model.active_cells = 1;
+            model.drop_cells[0] = model.xy_to_idx(12, 20);
+            for _ in 0..6_000 {
+                model.add_grain();
+            }
+            assert!(
+                (0..model.cells.len()).all(|idx| model.energy[idx] < model.threshold(idx) as f32)
+            );
+            assert!(model.cells.iter().all(|cell| cell.grains == 0));
+            // energy is either on the table or lost
+            let on_table: f64 = model.energy.iter().map(|&energy| energy as f64).sum();
+            assert!((on_table + model.lost_energy - added).abs() < added * 1.0e-4);
+            assert!(model.histogram.avalanches() > 0);
+            model
+        };
+        let zhang = grow(Rule::Zhang, ALPHA);
+        assert!(zhang.lost_energy > 0.0);
+        // OFC loses energy at every collapse unless alpha is 1 / neighbors
+        let ofc = grow(Rule::Ofc, ALPHA);
+        assert!(!ofc.is_conservative());
+        assert!(ofc.histogram.lost.keys().any(|&lost| lost > 0));
+        // without coupling a collapse only empties the drop cell
+        let uncoupled = grow(Rule::Ofc, 0.0);
+        assert_eq!(uncoupled.cells.iter().filter(|cell| cell.borged).count(), 1);
+        assert_eq!(uncoupled.histogram.largest(), 1);
+        // the palette blends between neighboring hues
+        let hues = Hues::default();
+        assert_eq!(hues.energy_hue(1.0), hues.grains[1]);
+        let blend = hues.energy_hue(0.5);
+        assert!((blend.g - (hues.grains[0].g + hues.grains[1].g) / 2.0).abs() < 1.0e-6);
+    }
+
+    #[test]
+    fn changed_cells() {
+        let mut model = Model::new(20, 16);
+        model.active_cells = 1;
+        model.drop_cells[0] = model.xy_to_idx(8, 6);
+        // a new model has every cell to draw
+        assert!(model.take_changed().iter().all(|&span| span == (0, 19)));
+        let before = model.cells.clone();
+        for _ in 0..60 {
+            model.add_grain();
+        }
+        let changed = model.take_changed();
+        for (y, &(first, last)) in changed.iter().enumerate() {
+            for x in 0..model.width {
+                let idx = model.xy_to_idx(x, y);
+                if model.cells[idx].grains != before[idx].grains || model.cells[idx].borged {
+                    assert!(first <= x && x <= last, "({}, {})", x, y);
+                }
+            }
+        }
+        assert!(changed.iter().any(|&(first, last)| first > last));
+        // nothing changes until the next grain
+        assert!(model
+            .take_changed()
+            .iter()
+            .all(|&(first, last)| first > last));
+        // a collapse on a periodic edge reaches the far side
+        model.boundary_x = Boundary::Periodic;
+        model.drop_cells[0] = model.xy_to_idx(0, 12);
+        while model.add_grain().topples == 0 {}
+        assert_eq!(model.take_changed()[12], (0, 19));
+    }
+
+    #[test]
+    fn source_tracking() {
+        let mut model = Model::new(40, 32);
+        model.active_cells = 2;
+        model.drop_cells[0] = model.xy_to_idx(10, 16);
+        model.drop_cells[1] = model.xy_to_idx(30, 16);
+        model.track_sources(true);
+        for _ in 0..600 {
+            model.drop_grain();
+        }
+        let (left, right) = (model.drop_cells[0], model.drop_cells[1]);
+        assert_eq!((model.sources[left].0, model.sources[right].0), (0, 1));
+        assert_eq!(model.sources[model.xy_to_idx(0, 0)].0, UNFED);
+        // each pile owns its side of the seam between them
+        for idx in (0..model.cells.len()).filter(|&idx| model.cells[idx].borged) {
+            match model.idx_to_xy(idx) {
+                (x, _) if x < 18 => assert_eq!(model.sources[idx].0, 0),
+                (x, _) if x > 22 => assert_eq!(model.sources[idx].0, 1),
+                _ => assert!(model.sources[idx].0 < 2),
+            }
+        }
+        let hue = model.hues.grain_hue(model.cells[left].grains);
+        assert_eq!(model.cell_hue(left), Hues::tint(hue, 0));
+        assert_ne!(Hues::source_hue(0), Hues::source_hue(1));
+        // removing a drop cell forgets the cells it fed and renumbers the rest - both are drawn again
+        model.take_changed();
+        model.remove_drop_cell(0);
+        assert_eq!((model.sources[left].0, model.sources[right].0), (UNFED, 0));
+        let changed = model.take_changed();
+        for idx in [left, right] {
+            let (x, y) = model.idx_to_xy(idx);
+            assert!(changed[y].0 <= x && x <= changed[y].1, "{:?}", changed[y]);
+        }
+        assert!(changed[0].0 > changed[0].1);
+        model.track_sources(false);
+        assert!(model.sources.is_empty());
+        assert_eq!(model.cell_hue(left), hue);
+    }
+
+    #[test]
+    fn render_raster() {
+        let mut model = Model::new(10, 8);
+        model.active_cells = 1;
+        model.drop_cells[0] = model.xy_to_idx(4, 4);
+        for _ in 0..2 {
+            model.add_grain();
+        }
+        // the raster covers the region asked for - pixels beyond the lattice are blank
+        let raster = model.render(2, 2, 12, 4);
+        assert_eq!(
+            (raster.width, raster.height, raster.bytes.len()),
+            (12, 4, 12 * 4 * 4)
+        );
+        assert_eq!(raster.get_pixel(2, 2), model.hues.grains[2].to_bytes());
+        assert_eq!(raster.get_pixel(0, 0), model.hues.untouched.to_bytes());
+        assert_eq!(raster.get_pixel(11, 0), BLANK.to_bytes());
+        // hexagonal cells are two pixels wide with odd rows shifted by one pixel
+        model.lattice = Lattice::Hexagonal;
+        let raster = model.render(0, 0, 10, 8);
+        assert_eq!(raster.width, 21);
+        assert_eq!(raster.get_pixel(8, 4), raster.get_pixel(9, 4));
+        assert_eq!(raster.get_pixel(9, 4), model.hues.grains[2].to_bytes());
+    }
+
+    // checksum() hashes the grains and touched state of every cell with FNV-1a
+    fn checksum(model: &Model) -> u64 {
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        for cell in model.cells.iter() {
+            for byte in [cell.grains, cell.borged as u8] {
+                hash ^= byte as u64;
+                hash = hash.wrapping_mul(0x0100_0000_01b3);
+            }
+        }
+        hash
+    }
+
+    #[test]
+    fn engine_matches_baseline() {
+        // lattices built by the recursive engine this one replaced, each drop cell receiving one grain in turn - the
+        // width, height, drop cells, grains, checksum, lost grains, and collapses of each
+        type Golden = (
+            usize,
+            usize,
+            &'static [(usize, usize)],
+            usize,
+            u64,
+            usize,
+            usize,
+        );
+        let golden: [Golden; 3] = [
+            (
+                64,
+                48,
+                &[(20, 24), (41, 30)],
+                4_000,
+                9_832_107_939_103_967_051,
+                13,
+                203_622,
+            ),
+            (
+                31,
+                31,
+                &[(15, 15)],
+                3_000,
+                5_434_088_950_098_547_770,
+                972,
+                148_162,
+            ),
+            (
+                101,
+                75,
+                &[(10, 10), (50, 37), (90, 60)],
+                12_000,
+                17_063_293_716_189_802_563,
+                3_668,
+                679_259,
+            ),
+        ];
+        for (width, height, drops, grains, hash, lost, topples) in golden {
+            let mut model = Model::new(width, height);
+            for (i, &(x, y)) in drops.iter().enumerate() {
+                model.drop_cells[i] = model.xy_to_idx(x, y);
+            }
+            model.active_cells = drops.len();
+            let collapses: usize = (0..grains).map(|_| model.drop_grain().topples).sum();
+            assert_eq!(
+                (checksum(&model), model.lost_grains, collapses),
+                (hash, lost, topples),
+                "{}x{}",
+                width,
+                height
+            );
+        }
+    }
 }
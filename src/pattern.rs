@@ -0,0 +1,418 @@
+use crate::{Model, Seams};
+
+use std::f32::consts::PI;
+
+pub const PERIOD_WINDOW: usize = 64; // width and height of the region searched for periodic patterns - a power of two
+const PEAKS: usize = 3; // strongest periods reported for each region
+const MAX_RADIUS: usize = 512; // symmetry is measured at most this far from a drop cell
+
+/// A Symmetry scores how closely a pile matches itself when rotated or reflected about its drop cell
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Symmetry {
+    pub pile: usize,
+    pub x: usize, // drop cell
+    pub y: usize,
+    pub radius: usize, // half the width of the square compared - the pile reaches at least this far on every axis
+    pub score: f32, // fraction of cells that match under the seven rotations and reflections of D4 - 1 is perfect
+}
+
+/// A Period is a repeating pattern found near the seam between two piles
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Period {
+    pub piles: (usize, usize), // drop cells on either side of the region
+    pub x: usize,              // center of the region
+    pub y: usize,
+    pub period: f32,    // cells from one repeat to the next
+    pub direction: f32, // degrees from the x axis the pattern repeats along
+    pub power: f32,     // share of the region's variation this period accounts for
+}
+
+/// A Dimension is the box-counting dimension of the cells holding some numbers of grains - the threads
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Dimension {
+    pub grains: Vec<u8>,
+    pub dimension: Option<f32>, // slope of log boxes against log 1 / box size - None without enough box sizes
+    pub boxes: Vec<(usize, usize)>, // box size and number of boxes holding at least one cell
+}
+
+impl Dimension {
+    /// label() lists the grain counts of the threads as 0/1/2
+    pub fn label(&self) -> String {
+        self.grains
+            .iter()
+            .map(|grains| grains.to_string())
+            .collect::<Vec<String>>()
+            .join("/")
+    }
+}
+
+/// Patterns are the symmetry, periodicity, and fractal measures of a model
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Patterns {
+    pub total_grains: usize, // grains on the lattice when measured
+    pub symmetry: Vec<Symmetry>,
+    pub periods: Vec<Period>,
+    pub dimensions: Vec<Dimension>,
+}
+impl Patterns {
+    /// measure() finds the patterns of model - seams are found between tracked sources or else halfway between piles
+    pub fn measure(model: &Model) -> Self {
+        let symmetry = (0..model.active_cells)
+            .map(|pile| symmetry(model, pile))
+            .collect();
+        let regions: Vec<((usize, usize), (usize, usize))> = match model.sources.is_empty() {
+            false => Seams::find(model)
+                .seams
+                .iter()
+                .map(|seam| {
+                    let center = (
+                        seam.center.0.round() as usize,
+                        seam.center.1.round() as usize,
+                    );
+                    (seam.piles, center)
+                })
+                .collect(),
+            true => (0..model.active_cells)
+                .flat_map(|a| (a + 1..model.active_cells).map(move |b| (a, b)))
+                .map(|(a, b)| {
+                    let (ax, ay) = model.idx_to_xy(model.drop_cells[a]);
+                    let (bx, by) = model.idx_to_xy(model.drop_cells[b]);
+                    ((a, b), ((ax + bx) / 2, (ay + by) / 2))
+                })
+                .collect(),
+        };
+        let periods = regions
+            .into_iter()
+            .flat_map(|(piles, (x, y))| periods(model, piles, x, y))
+            .collect();
+        let dimensions = [vec![0], vec![1], vec![2], vec![0, 1, 2]]
+            .into_iter()
+            .map(|grains| dimension(model, grains))
+            .collect();
+        Self {
+            total_grains: model.total_grains,
+            symmetry,
+            periods,
+            dimensions,
+        }
+    }
+    /// to_csv() lists every measure as metric, subject, value, and detail - one row per measure
+    pub fn to_csv(&self) -> String {
+        let mut csv = "metric,subject,value,detail\n".to_string();
+        for symmetry in self.symmetry.iter() {
+            csv.push_str(&format!(
+                "symmetry,pile {} at {} {},{:.4},radius {}\n",
+                symmetry.pile, symmetry.x, symmetry.y, symmetry.score, symmetry.radius
+            ));
+        }
+        for period in self.periods.iter() {
+            csv.push_str(&format!(
+                "period,seam {}-{} at {} {},{:.2},direction {:.1} power {:.3}\n",
+                period.piles.0,
+                period.piles.1,
+                period.x,
+                period.y,
+                period.period,
+                period.direction,
+                period.power
+            ));
+        }
+        for dimension in self.dimensions.iter() {
+            let value = dimension
+                .dimension
+                .map_or("".to_string(), |dimension| format!("{:.3}", dimension));
+            let largest = dimension.boxes.last().map_or(0, |&(size, _)| size);
+            csv.push_str(&format!(
+                "dimension,{} grains,{},boxes from 1 to {}\n",
+                dimension.label(),
+                value,
+                largest
+            ));
+        }
+        csv
+    }
+}
+// level() returns the grains of cell idx - the whole grains of energy for the continuous rules
+fn level(model: &Model, idx: usize) -> u8 {
+    match model.rule.is_continuous() {
+        true => model.energy.get(idx).map_or(0, |&energy| energy as u8),
+        false => model.cells[idx].grains,
+    }
+}
+// symmetry() compares the square around a drop cell with its rotations and reflections - the square reaches as
+// far as the pile does on its shortest axis so the lattice beyond the pile doesn't count
+fn symmetry(model: &Model, pile: usize) -> Symmetry {
+    let (x, y) = model.idx_to_xy(model.drop_cells[pile]);
+    let (cx, cy) = (x as i64, y as i64);
+    let reach = |dx: i64, dy: i64| {
+        (1..)
+            .take_while(|&r| {
+                let (px, py) = (cx + dx * r, cy + dy * r);
+                px >= 0
+                    && py >= 0
+                    && (px as usize) < model.width
+                    && (py as usize) < model.height
+                    && model.cells[model.xy_to_idx(px as usize, py as usize)].borged
+            })
+            .count()
+    };
+    let radius = [(1, 0), (-1, 0), (0, 1), (0, -1)]
+        .into_iter()
+        .map(|(dx, dy)| reach(dx, dy))
+        .fold(MAX_RADIUS, usize::min);
+    let r = radius as i64;
+    let at = |dx: i64, dy: i64| {
+        level(
+            model,
+            model.xy_to_idx((cx + dx) as usize, (cy + dy) as usize),
+        )
+    };
+    let (mut matches, mut comparisons) = (0_usize, 0_usize);
+    for dy in -r..=r {
+        for dx in -r..=r {
+            let grains = at(dx, dy);
+            for (tx, ty) in [
+                (-dx, dy),
+                (dx, -dy),
+                (-dx, -dy),
+                (dy, dx),
+                (-dy, dx),
+                (dy, -dx),
+                (-dy, -dx),
+            ] {
+                matches += (at(tx, ty) == grains) as usize;
+                comparisons += 1;
+            }
+        }
+    }
+    Symmetry {
+        pile,
+        x,
+        y,
+        radius,
+        score: matches as f32 / comparisons as f32,
+    }
+}
+// periods() finds the strongest repeating patterns in the grains of the window centered on (x, y)
+fn periods(model: &Model, piles: (usize, usize), x: usize, y: usize) -> Vec<Period> {
+    let n = PERIOD_WINDOW;
+    if model.width < n || model.height < n {
+        return Vec::new();
+    }
+    // the window stays on the lattice
+    let left = x.saturating_sub(n / 2).min(model.width - n);
+    let top = y.saturating_sub(n / 2).min(model.height - n);
+    let field: Vec<f32> = (0..n * n)
+        .map(|i| level(model, model.xy_to_idx(left + i % n, top + i / n)) as f32)
+        .collect();
+    spectrum_peaks(&field, n)
+        .into_iter()
+        .map(|(period, direction, power)| Period {
+            piles,
+            x: left + n / 2,
+            y: top + n / 2,
+            period,
+            direction,
+            power,
+        })
+        .collect()
+}
+// spectrum_peaks() returns the period, direction, and share of power of the strongest peaks of the 2D power spectrum
+// of an n by n field - the field is tapered at its edges so the window itself doesn't look like a pattern
+fn spectrum_peaks(field: &[f32], n: usize) -> Vec<(f32, f32, f32)> {
+    let mean = field.iter().sum::<f32>() / field.len() as f32;
+    let taper = |i: usize| 0.5 - 0.5 * (2.0 * PI * i as f32 / (n - 1) as f32).cos();
+    let mut values: Vec<(f32, f32)> = field
+        .iter()
+        .enumerate()
+        .map(|(i, value)| ((value - mean) * taper(i % n) * taper(i / n), 0.0))
+        .collect();
+    for row in values.chunks_mut(n) {
+        fft(row);
+    }
+    let mut column = vec![(0.0, 0.0); n];
+    for x in 0..n {
+        for y in 0..n {
+            column[y] = values[y * n + x];
+        }
+        fft(&mut column);
+        for y in 0..n {
+            values[y * n + x] = column[y];
+        }
+    }
+    let power: Vec<f32> = values.iter().map(|(re, im)| re * re + im * im).collect();
+    let total = power.iter().sum::<f32>() - power[0];
+    if total <= 0.0 {
+        return Vec::new();
+    }
+    let signed = |k: usize| match k < n / 2 {
+        true => k as f32,
+        false => k as f32 - n as f32,
+    };
+    // the eight bins around bin i - the spectrum wraps around at its edges
+    let around = |i: usize| {
+        let (kx, ky) = (i % n, i / n);
+        [
+            (1, 0),
+            (n - 1, 0),
+            (0, 1),
+            (0, n - 1),
+            (1, 1),
+            (n - 1, n - 1),
+            (1, n - 1),
+            (n - 1, 1),
+        ]
+        .map(|(dx, dy)| (ky + dy) % n * n + (kx + dx) % n)
+    };
+    // a real field has a mirror image peak for every peak - only half of the plane is searched
+    let mut peaks: Vec<(f32, f32, f32)> = (0..n * n)
+        .filter(|&i| {
+            let (fx, fy) = (signed(i % n), signed(i / n));
+            fy > 0.0 || (fy == 0.0 && fx > 0.0)
+        })
+        .filter(|&i| around(i).iter().all(|&j| power[i] >= power[j]))
+        .map(|i| {
+            let (fx, fy) = (signed(i % n), signed(i / n));
+            let period = n as f32 / (fx * fx + fy * fy).sqrt();
+            let direction = fy.atan2(fx).to_degrees().rem_euclid(180.0);
+            // the taper spreads a peak over the bins around it, and the mirror image peak holds as much again
+            let peak = power[i] + around(i).iter().map(|&j| power[j]).sum::<f32>();
+            (period, direction, 2.0 * peak / total)
+        })
+        .collect();
+    peaks.sort_by(|a, b| b.2.total_cmp(&a.2));
+    peaks.truncate(PEAKS);
+    peaks
+}
+// fft() replaces values with their discrete Fourier transform - the length must be a power of two
+fn fft(values: &mut [(f32, f32)]) {
+    let n = values.len();
+    // put the values in bit reversed order so each pass combines neighboring blocks
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            values.swap(i, j);
+        }
+    }
+    let mut len = 2;
+    while len <= n {
+        let angle = -2.0 * PI / len as f32;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (wr, wi) = ((angle * k as f32).cos(), (angle * k as f32).sin());
+                let (ur, ui) = values[start + k];
+                let (xr, xi) = values[start + k + len / 2];
+                let (vr, vi) = (xr * wr - xi * wi, xr * wi + xi * wr);
+                values[start + k] = (ur + vr, ui + vi);
+                values[start + k + len / 2] = (ur - vr, ui - vi);
+            }
+        }
+        len <<= 1;
+    }
+}
+// dimension() counts the boxes of each size that hold a touched cell with one of the grain counts
+fn dimension(model: &Model, grains: Vec<u8>) -> Dimension {
+    let (left, top, width, height) = model.find_extent();
+    let (left, top, width, height) = (left as usize, top as usize, width as usize, height as usize);
+    let occupied: Vec<bool> = (0..width * height)
+        .map(|i| {
+            let idx = model.xy_to_idx(left + i % width, top + i / width);
+            model.cells[idx].borged && grains.contains(&level(model, idx))
+        })
+        .collect();
+    let boxes = box_counts(occupied, width, height);
+    Dimension {
+        grains,
+        dimension: box_dimension(&boxes),
+        boxes,
+    }
+}
+// box_counts() counts the occupied boxes of width 1, 2, 4, ... - each size merges two by two boxes of the last
+// until a quarter of the shorter side
+fn box_counts(occupied: Vec<bool>, width: usize, height: usize) -> Vec<(usize, usize)> {
+    let mut boxes = Vec::new();
+    let (mut grid, mut w, mut h, mut size) = (occupied, width, height, 1);
+    while size <= width.min(height) / 4 {
+        boxes.push((size, grid.iter().filter(|&&occupied| occupied).count()));
+        let (next_w, next_h) = (w.div_ceil(2), h.div_ceil(2));
+        let mut next = vec![false; next_w * next_h];
+        for (i, _) in grid.iter().enumerate().filter(|(_, &occupied)| occupied) {
+            next[(i / w) / 2 * next_w + (i % w) / 2] = true;
+        }
+        (grid, w, h, size) = (next, next_w, next_h, size * 2);
+    }
+    boxes
+}
+// box_dimension() fits log boxes against log 1 / size by least squares - None without three sizes holding cells
+fn box_dimension(boxes: &[(usize, usize)]) -> Option<f32> {
+    let points: Vec<(f32, f32)> = boxes
+        .iter()
+        .filter(|&&(_, count)| count > 0)
+        .map(|&(size, count)| (-(size as f32).ln(), (count as f32).ln()))
+        .collect();
+    if points.len() < 3 {
+        return None;
+    }
+    let count = points.len() as f32;
+    let mx = points.iter().map(|(x, _)| x).sum::<f32>() / count;
+    let my = points.iter().map(|(_, y)| y).sum::<f32>() / count;
+    let sxy: f32 = points.iter().map(|(x, y)| (x - mx) * (y - my)).sum();
+    let sxx: f32 = points.iter().map(|(x, _)| (x - mx) * (x - mx)).sum();
+    Some(sxy / sxx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Rule;
+
+    #[test]
+    fn pattern_measures() {
+        // a single BTW pile is as symmetric as the square lattice
+        let mut model = Model::new(80, 80);
+        model.active_cells = 1;
+        model.drop_cells[0] = model.calc_center_idx();
+        for _ in 0..4_000 {
+            model.add_grain();
+        }
+        let patterns = Patterns::measure(&model);
+        assert_eq!(patterns.symmetry.len(), 1);
+        assert!(patterns.symmetry[0].radius > 10);
+        assert_eq!(patterns.symmetry[0].score, 1.0);
+        // a random rule breaks the symmetry
+        let mut manna = Model::new(80, 80);
+        manna.rule = Rule::Manna;
+        manna.active_cells = 1;
+        manna.drop_cells[0] = manna.calc_center_idx();
+        for _ in 0..4_000 {
+            manna.add_grain();
+        }
+        assert!(Patterns::measure(&manna).symmetry[0].score < 0.9);
+        // stripes eight cells apart along x
+        let n = PERIOD_WINDOW;
+        let stripes: Vec<f32> = (0..n * n).map(|i| ((i % n) % 8 < 4) as u8 as f32).collect();
+        let peaks = spectrum_peaks(&stripes, n);
+        assert_eq!((peaks[0].0, peaks[0].1), (8.0, 0.0));
+        assert!(peaks[0].2 > 0.5);
+        // a filled square is two dimensional and a line is one dimensional
+        let boxes = box_counts(vec![true; 64 * 64], 64, 64);
+        assert_eq!(
+            boxes,
+            vec![(1, 4_096), (2, 1_024), (4, 256), (8, 64), (16, 16)]
+        );
+        assert!((box_dimension(&boxes).unwrap() - 2.0).abs() < 1e-4);
+        let line: Vec<bool> = (0..64 * 64).map(|i| i / 64 == 10).collect();
+        assert!((box_dimension(&box_counts(line, 64, 64)).unwrap() - 1.0).abs() < 1e-4);
+        assert_eq!(box_dimension(&[(1, 5)]), None);
+        // one row per pile, period, and set of threads
+        let rows = 1 + patterns.symmetry.len() + patterns.periods.len() + 4;
+        assert_eq!(patterns.to_csv().lines().count(), rows);
+        assert!(patterns.to_csv().contains("\ndimension,0/1/2 grains,"));
+    }
+}
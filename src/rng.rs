@@ -0,0 +1,57 @@
+/// An Rng is a small seedable random number generator so stochastic models can be reproduced exactly
+// splitmix64 (Steele, Lea & Flood 2014) - any seed, including zero, gives a full length sequence
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Rng {
+    pub state: u64,
+}
+impl Rng {
+    /// new() starts a sequence from seed
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+    /// next_u64() returns the next 64 random bits
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+    /// below() returns a number from 0 up to, but not including, n
+    pub fn below(&mut self, n: usize) -> usize {
+        // multiply and keep the high bits - the bias is far too small to matter for n this size
+        ((self.next_u64() as u128 * n as u128) >> 64) as usize
+    }
+    /// unit() returns a number from 0.0 up to, but not including, 1.0
+    pub fn unit(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1_u64 << 53) as f64
+    }
+    /// chance() is true with probability p
+    pub fn chance(&mut self, p: f32) -> bool {
+        self.unit() < p as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sequences() {
+        // the same seed repeats the same sequence
+        let mut first = Rng::new(42);
+        let mut second = Rng::new(42);
+        for _ in 0..100 {
+            assert_eq!(first.next_u64(), second.next_u64());
+        }
+        assert_ne!(Rng::new(1).next_u64(), Rng::new(2).next_u64());
+        // below() covers its whole range evenly
+        let mut rng = Rng::new(7);
+        let mut counts = [0_usize; 6];
+        for _ in 0..60_000 {
+            counts[rng.below(6)] += 1;
+        }
+        assert!(counts.iter().all(|&count| count > 9_500 && count < 10_500));
+        assert!((0..1_000).all(|_| !rng.chance(0.0) && rng.chance(1.0)));
+    }
+}
@@ -0,0 +1,540 @@
+use crate::{
+    Growth, Journal, Model, Patterns, Pile, PowerLaw, Scenario, Seams, Snapshots, Sweep, FIT_MIN,
+};
+
+use std::fs;
+use std::str::FromStr;
+use std::time::Instant;
+
+const USAGE: &str = "Usage: lakhesis [COMMAND] [OPTIONS]
+
+Without a command lakhesis opens the simulation window. lakhesis FILE... opens the window with a saved
+.lak or .lkb model loaded or a .toml scenario ready to start - a folder adds its saved models to the list
+shown by [H].
+
+Commands:
+  run     Run a simulation without a window
+  sweep   Run every combination of piles, separations, sizes, and thresholds
+  convert Convert a saved model between formats - lakhesis convert IN OUT, where a .lkb name means binary
+  help    Show this message
+
+Options for run:
+  --scenario FILE     start from a TOML scenario - the options after it change the scenario
+  --replay FILE       repeat the simulation recorded in a journal - only --grains and the files can be set
+  --width N           lattice width (default 3000)
+  --height N          lattice height (default: the width)
+  --drops X,Y         add a drop cell - repeat for more piles (default: the center)
+  --grains N          number of grains to drop (default 16777216)
+  --critical N        grains that collapse a cell (default: one per neighbor)
+  --lattice NAME      square, moore, hexagonal, or triangular
+  --boundary NAME     open, closed, or periodic for every edge
+  --boundary-x NAME   left and right edges only
+  --boundary-y NAME   top and bottom edges only
+  --rule NAME         btw, manna, zhang, or ofc
+  --spread N          random thresholds from critical to critical + N
+  --dissipation P     probability that a grain is lost in transit
+  --alpha A           OFC coupling - at most 1 / neighbors
+  --seed N            seed for the stochastic rules
+  --out FILE          save the final model as a .lak text or .lkb binary file
+  --png FILE          save an image of the active area
+  --sources           tint the image by the sandpile that fed each cell most
+  --seams FILE        save the length, width, and direction of the seams between piles as CSV
+  --seam-lines FILE   save the middle of each seam as CSV points
+  --seam-mask FILE    save an image of the seam cells
+  --patterns FILE     save the symmetry of each pile, periods near the seams, and thread dimensions as CSV
+  --stats FILE        save the avalanche histogram as CSV
+  --growth FILE       save the grains, area, radius, and extent of each pile as CSV
+  --growth-curve FILE save the radius of each pile as its grains were added as CSV
+  --journal FILE      save a journal that repeats the run exactly
+  --quiet             don't print progress
+
+Options for sweep (each takes a value, a list like 2,3,4, or a range like 50..200:50):
+  --piles LIST        number of sandpiles (default 2)
+  --separation LIST   cells between neighboring piles (default 100)
+  --size LIST         width and height of the lattice (default 500)
+  --critical LIST     grains that collapse a cell (default: one per neighbor)
+  --csv FILE          results table (default sweep.csv)
+  --images FOLDER     save an image of every run
+  --threads N         runs at the same time (default: one per CPU core)
+Other run options apply to every run of the sweep - --grains defaults to 100000";
+
+/// A Run is a simulation driven from the command line instead of the window
+#[derive(Clone, Debug)]
+pub struct Run {
+    pub model: Model,
+    pub grains: usize, // stop once this many grains have been dropped
+    pub snapshots: Snapshots,
+    pub out: Option<String>,
+    pub png: Option<String>,
+    pub stats: Option<String>,
+    pub seams: Option<String>,
+    pub seam_lines: Option<String>,
+    pub seam_mask: Option<String>,
+    pub patterns: Option<String>,
+    pub growth: Option<String>,
+    pub growth_curve: Option<String>,
+    pub journal: Option<String>,
+    pub replay: Journal, // the empty lattice the run started from and the changes to make as the grains fall
+    pub quiet: bool,
+}
+impl Run {
+    /// parse() builds a run from the arguments that follow "run" - options after --scenario change the scenario
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        let mut scenario = Scenario::default();
+        let mut height: Option<usize> = None;
+        let (mut out, mut png, mut stats, mut journal) = (None, None, None, None);
+        let (mut seams, mut seam_lines, mut seam_mask, mut patterns) = (None, None, None, None);
+        let (mut growth, mut growth_curve) = (None, None);
+        let mut replay: Option<Journal> = None;
+        let mut grains: Option<usize> = None;
+        let (mut sources, mut quiet) = (false, false);
+        let mut args = args.iter();
+        while let Some(flag) = args.next() {
+            let mut next = || args.next().ok_or(format!("{} needs a value", flag));
+            // a journal already says how the lattice was set up
+            if replay.is_some()
+                && !matches!(
+                    flag.as_str(),
+                    "--grains"
+                        | "--out"
+                        | "--png"
+                        | "--sources"
+                        | "--seams"
+                        | "--seam-lines"
+                        | "--seam-mask"
+                        | "--patterns"
+                        | "--stats"
+                        | "--growth"
+                        | "--growth-curve"
+                        | "--journal"
+                        | "--quiet"
+                )
+            {
+                return Err(format!("{} can't change a replay", flag));
+            }
+            match flag.as_str() {
+                "--replay" => {
+                    let filename = next()?;
+                    replay = Some(
+                        Journal::load(filename)
+                            .map_err(|why| format!("Error reading {}: {}", filename, why))?,
+                    );
+                }
+                "--scenario" => {
+                    scenario = Scenario::load(next()?)?;
+                    height = Some(scenario.height);
+                    grains = None;
+                }
+                "--width" => scenario.width = value(flag, next()?)?,
+                "--height" => height = Some(value(flag, next()?)?),
+                "--drops" => {
+                    let (x, y) = pair(next()?)?;
+                    scenario.piles.push(Pile {
+                        x,
+                        y,
+                        rate: 1,
+                        start: 0,
+                        stop: None,
+                    });
+                }
+                "--grains" => grains = Some(value(flag, next()?)?),
+                "--critical" => scenario.critical = Some(value(flag, next()?)?),
+                "--lattice" => scenario.lattice = value(flag, next()?)?,
+                "--boundary" => {
+                    scenario.boundary_x = value(flag, next()?)?;
+                    scenario.boundary_y = scenario.boundary_x;
+                }
+                "--boundary-x" => scenario.boundary_x = value(flag, next()?)?,
+                "--boundary-y" => scenario.boundary_y = value(flag, next()?)?,
+                "--rule" => scenario.rule = value(flag, next()?)?,
+                "--spread" => scenario.spread = value(flag, next()?)?,
+                "--dissipation" => scenario.dissipation = value(flag, next()?)?,
+                "--alpha" => scenario.alpha = value(flag, next()?)?,
+                "--seed" => scenario.seed = value(flag, next()?)?,
+                "--out" => out = Some(next()?.clone()),
+                "--png" => png = Some(next()?.clone()),
+                "--sources" => sources = true,
+                "--stats" => stats = Some(next()?.clone()),
+                "--seams" => seams = Some(next()?.clone()),
+                "--seam-lines" => seam_lines = Some(next()?.clone()),
+                "--seam-mask" => seam_mask = Some(next()?.clone()),
+                "--patterns" => patterns = Some(next()?.clone()),
+                "--growth" => growth = Some(next()?.clone()),
+                "--growth-curve" => growth_curve = Some(next()?.clone()),
+                "--journal" => journal = Some(next()?.clone()),
+                "--quiet" => quiet = true,
+                _ => return Err(format!("Unknown option '{}'", flag)),
+            }
+        }
+        // seams are found between the territories of the piles
+        let sources = sources || seams.is_some() || seam_lines.is_some() || seam_mask.is_some();
+        if let Some(replay) = replay {
+            let mut model = replay.model().map_err(|why| why.to_string())?;
+            model.track_sources(sources);
+            return Ok(Self {
+                model,
+                grains: grains.unwrap_or(replay.total_grains),
+                snapshots: Snapshots::default(),
+                out,
+                png,
+                stats,
+                seams,
+                seam_lines,
+                seam_mask,
+                patterns,
+                growth,
+                growth_curve,
+                journal,
+                replay,
+                quiet,
+            });
+        }
+        scenario.height = height.unwrap_or(scenario.width);
+        scenario.grains = grains.unwrap_or(scenario.grains);
+        let mut model = scenario.build()?;
+        if model.active_cells == 0 {
+            model.drop_cells[0] = model.calc_center_idx();
+            model.active_cells = 1;
+        }
+        model.track_sources(sources);
+        Ok(Self {
+            replay: Journal::new(&model).expect("A new model is empty"),
+            model,
+            // a scenario without a limit runs until every pile stops
+            grains: match scenario.grains {
+                0 => usize::MAX,
+                grains => grains,
+            },
+            snapshots: scenario.snapshots,
+            out,
+            png,
+            stats,
+            seams,
+            seam_lines,
+            seam_mask,
+            patterns,
+            growth,
+            growth_curve,
+            journal,
+            quiet,
+        })
+    }
+    /// execute() simulates, writes the requested files, and prints a summary
+    pub fn execute(&mut self) -> Result<(), String> {
+        self.simulate()?;
+        self.save()?;
+        println!("{}", self.summary());
+        Ok(())
+    }
+    /// simulate() drops the grains and takes the scheduled snapshots, printing progress as it goes unless quiet
+    pub fn simulate(&mut self) -> Result<(), String> {
+        let start = Instant::now();
+        let step = (self.grains / 100).max(1);
+        let mut operations = self.replay.operations.iter().peekable();
+        loop {
+            // changes from a journal are made once the lattice holds as many grains as when they were made
+            while let Some(operation) =
+                operations.next_if(|operation| operation.at() <= self.model.total_grains)
+            {
+                operation.apply(&mut self.model);
+            }
+            if self.model.total_grains >= self.grains {
+                break;
+            }
+            self.model.drop_grain();
+            if self.model.saturated {
+                if !self.quiet {
+                    eprintln!("The lattice is saturated - stopping early");
+                }
+                break;
+            }
+            let total_grains = self.model.total_grains;
+            if self.snapshots.is_due(total_grains - 1, total_grains) {
+                self.snapshot(&self.snapshots.filename(total_grains))?;
+            }
+            if self.model.is_idle() {
+                if !self.quiet {
+                    eprintln!("Every sandpile has stopped - stopping early");
+                }
+                break;
+            }
+            if !self.quiet && total_grains.is_multiple_of(step) {
+                eprintln!(
+                    "{:3}% {:12} grains {:8.1}s",
+                    total_grains * 100 / self.grains,
+                    total_grains,
+                    start.elapsed().as_secs_f32()
+                );
+            }
+        }
+        Ok(())
+    }
+    /// save() writes the model, image, and histogram files that were asked for
+    pub fn save(&self) -> Result<(), String> {
+        if let Some(filename) = &self.out {
+            self.model
+                .curate_as(filename)
+                .map_err(|why| format!("Error writing {}: {}", filename, why))?;
+        }
+        if let Some(filename) = &self.png {
+            self.snapshot(filename)?;
+        }
+        if let Some(filename) = &self.stats {
+            fs::write(filename, self.model.histogram.to_csv())
+                .map_err(|why| format!("Error writing {}: {}", filename, why))?;
+        }
+        if self.seams.is_some() || self.seam_lines.is_some() || self.seam_mask.is_some() {
+            let seams = Seams::find(&self.model);
+            for (filename, csv) in [
+                (&self.seams, seams.to_csv()),
+                (&self.seam_lines, seams.polylines_csv()),
+            ] {
+                if let Some(filename) = filename {
+                    fs::write(filename, csv)
+                        .map_err(|why| format!("Error writing {}: {}", filename, why))?;
+                }
+            }
+            if let Some(filename) = &self.seam_mask {
+                seams
+                    .mask()
+                    .export_png(filename)
+                    .map_err(|why| format!("Error writing {}: {}", filename, why))?;
+            }
+        }
+        if let Some(filename) = &self.patterns {
+            fs::write(filename, Patterns::measure(&self.model).to_csv())
+                .map_err(|why| format!("Error writing {}: {}", filename, why))?;
+        }
+        for (filename, csv) in [
+            (&self.growth, Growth::to_csv(&self.model)),
+            (&self.growth_curve, Growth::curves_csv(&self.model)),
+        ] {
+            if let Some(filename) = filename {
+                fs::write(filename, csv)
+                    .map_err(|why| format!("Error writing {}: {}", filename, why))?;
+            }
+        }
+        if let Some(filename) = &self.journal {
+            let total_grains = self.model.total_grains;
+            Journal {
+                operations: self
+                    .replay
+                    .operations
+                    .iter()
+                    .copied()
+                    .filter(|operation| operation.at() <= total_grains)
+                    .collect(),
+                total_grains,
+                ..self.replay.clone()
+            }
+            .save(filename)
+            .map_err(|why| format!("Error writing {}: {}", filename, why))?;
+        }
+        Ok(())
+    }
+    /// snapshot() saves an image of the active area
+    pub fn snapshot(&self, filename: &str) -> Result<(), String> {
+        let (min_x, min_y, extent_width, extent_height) = self.model.find_extent();
+        self.model
+            .render(min_x, min_y, extent_width, extent_height)
+            .export_png(filename)
+            .map_err(|why| format!("Error writing {}: {}", filename, why))
+    }
+    /// summary() describes the grains, avalanches, piles, and seams of the run
+    pub fn summary(&self) -> String {
+        let histogram = &self.model.histogram;
+        let lost = match self.model.rule.is_continuous() {
+            true => format!("{:.1}", self.model.lost_energy),
+            false => self.model.lost_grains.to_string(),
+        };
+        let mut summary = format!(
+            "grains: {}\nlost: {}\navalanches: {}\nlargest: {}",
+            self.model.total_grains,
+            lost,
+            histogram.avalanches(),
+            histogram.largest()
+        );
+        for (name, counts) in [
+            ("size", &histogram.topples),
+            ("duration", &histogram.duration),
+        ] {
+            if let Some(fit) = PowerLaw::fit(counts, FIT_MIN) {
+                summary.push_str(&format!(
+                    "\n{} exponent: {:.3} +/- {:.3} cutoff: {:.1}",
+                    name, fit.exponent, fit.error, fit.cutoff
+                ));
+            }
+        }
+        for (i, growth) in self.model.growth.iter().enumerate() {
+            summary.push_str(&format!(
+                "\npile {}: grains {} area {} radius {:.1}",
+                i, growth.grains, growth.area, growth.radius
+            ));
+            if let Some(exponent) = growth.exponent() {
+                summary.push_str(&format!(" growth exponent {:.3}", exponent));
+            }
+        }
+        for seam in Seams::find(&self.model).seams {
+            summary.push_str(&format!(
+                "\nseam {}-{}: length {:.1} width {:.2} orientation {:.1}",
+                seam.piles.0, seam.piles.1, seam.length, seam.width, seam.orientation
+            ));
+        }
+        summary
+    }
+}
+
+/// command_line() runs the command named by the first argument and returns the process exit code
+pub fn command_line(args: &[String]) -> i32 {
+    let result = match args.first().map(String::as_str) {
+        Some("run") => Run::parse(&args[1..]).and_then(|mut run| run.execute()),
+        Some("sweep") => Sweep::parse(&args[1..]).and_then(|sweep| sweep.execute()),
+        Some("convert") => convert(&args[1..]),
+        Some("help") | Some("--help") | Some("-h") => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        Some(path) if std::path::Path::new(path).exists() => Err(format!(
+            "Opening '{}' needs the window - this build only has the command line tools",
+            path
+        )),
+        Some(command) => Err(format!("Unknown command '{}'\n\n{}", command, USAGE)),
+        None => Err(USAGE.to_string()),
+    };
+    match result {
+        Ok(()) => 0,
+        Err(why) => {
+            eprintln!("{}", why);
+            1
+        }
+    }
+}
+// convert() loads a model saved in either format and saves it again in the format named by the new extension
+fn convert(args: &[String]) -> Result<(), String> {
+    let [from, to] = args else {
+        return Err(format!(
+            "convert needs a file to read and a file to write\n\n{}",
+            USAGE
+        ));
+    };
+    let mut model = Model::new(1, 1);
+    model
+        .uncurate(from)
+        .map_err(|why| format!("Error reading {}: {}", from, why))?;
+    model
+        .curate_as(to)
+        .map_err(|why| format!("Error writing {}: {}", to, why))
+}
+// value() parses the value that follows a flag
+pub(crate) fn value<T: FromStr>(flag: &str, text: &str) -> Result<T, String> {
+    text.parse::<T>()
+        .map_err(|_| format!("'{}' is not a valid value for {}", text, flag))
+}
+// pair() parses the x,y coordinates of a drop cell
+fn pair(text: &str) -> Result<(usize, usize), String> {
+    match text.split_once(',') {
+        Some((x, y)) => Ok((value("--drops", x)?, value("--drops", y)?)),
+        None => Err(format!("'{}' is not a valid drop cell - use x,y", text)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Boundary;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn run_from_arguments() {
+        let mut run = Run::parse(&args(
+            "--width 40 --height 30 --drops 10,15 --drops 30,15 --grains 2000 --boundary periodic --quiet",
+        ))
+        .unwrap();
+        assert_eq!((run.model.width, run.model.height), (40, 30));
+        assert_eq!(run.model.active_cells, 2);
+        assert_eq!(run.model.drop_cells[1], run.model.xy_to_idx(30, 15));
+        assert_eq!(run.model.boundary_y, Boundary::Periodic);
+        assert_eq!(run.model.critical, 4);
+        run.execute().unwrap();
+        assert_eq!(run.model.total_grains, 2000);
+        assert_eq!(run.model.lost_grains, 0);
+        assert!(run.summary().starts_with("grains: 2000\nlost: 0"));
+        assert!(run.summary().contains("\npile 1: grains 1000 area"));
+        // the seam between the piles is summarized once sources are tracked
+        assert!(!run.summary().contains("seam"));
+        run.model.track_sources(true);
+        for _ in 0..2000 {
+            run.model.drop_grain();
+        }
+        assert!(run.summary().contains("\nseam 0-1: length"));
+        let seams = Run::parse(&args("--width 40 --seam-mask seams.png")).unwrap();
+        assert!(!seams.model.sources.is_empty());
+        // mistakes are reported rather than panicking
+        assert!(Run::parse(&args("--width 40 --drops 50,5")).is_err());
+        assert!(Run::parse(&args("--width forty")).is_err());
+        assert!(Run::parse(&args("--lattice hexagonal --critical 4")).is_err());
+        assert!(Run::parse(&args("--width 40 --rule ofc --alpha 0.6 --drops 20,20")).is_err());
+        assert!(Run::parse(&args("--width 40 --rule ofc --alpha 0.25 --drops 20,20")).is_ok());
+        assert!(Run::parse(&args("--grains")).is_err());
+        assert!(Run::parse(&args("--colour blue")).is_err());
+        assert_eq!(command_line(&args("shovel")), 1);
+        assert_eq!(command_line(&args("convert only_one.lak")), 1);
+    }
+
+    #[test]
+    fn run_from_scenario() {
+        let folder = std::env::temp_dir().join(format!("lakhesis_run_{}", std::process::id()));
+        fs::create_dir_all(&folder).unwrap();
+        let filename = folder.join("scenario.toml").display().to_string();
+        let prefix = folder.join("frame").display().to_string();
+        fs::write(
+            &filename,
+            format!(
+                "width = 60\nheight = 40\ngrains = 3000\n[snapshots]\nevery = 1000\nprefix = '{}'\n\
+                 [[pile]]\nx = 20\ny = 20\nstop = 1500\n[[pile]]\nx = 40\ny = 20\nrate = 2\nstop = 2500\n",
+                prefix
+            ),
+        )
+        .unwrap();
+        // options after the scenario change it
+        let journal = folder.join("run.journal").display().to_string();
+        let mut run = Run::parse(&args(&format!(
+            "--scenario {} --boundary closed --journal {} --quiet",
+            filename, journal
+        )))
+        .unwrap();
+        assert_eq!((run.model.width, run.model.height), (60, 40));
+        assert_eq!(run.model.boundary_x, Boundary::Closed);
+        assert_eq!(run.model.schedules[1].rate, 2);
+        run.execute().unwrap();
+        // the run ends once both piles have stopped
+        assert_eq!(run.model.total_grains, 2500);
+        for grains in [1000, 2000] {
+            assert!(fs::metadata(run.snapshots.filename(grains)).is_ok());
+        }
+        assert!(Run::parse(&args("--scenario missing.toml")).is_err());
+        // the journal repeats the run without the scenario
+        let mut replay = Run::parse(&args(&format!("--replay {} --quiet", journal))).unwrap();
+        replay.execute().unwrap();
+        assert_eq!(replay.model.total_grains, 2500);
+        assert!((0..run.model.cells.len())
+            .all(|i| replay.model.cells[i].grains == run.model.cells[i].grains));
+        assert!(Run::parse(&args(&format!("--replay {} --width 80", journal))).is_err());
+        // a saved model converts to the binary format and back
+        let text = folder.join("model.lak").display().to_string();
+        let binary = folder.join("model.lkb").display().to_string();
+        run.model.curate_as(&text).unwrap();
+        assert_eq!(
+            command_line(&[String::from("convert"), text.clone(), binary.clone()]),
+            0
+        );
+        let mut converted = Model::new(1, 1);
+        converted.uncurate(&binary).unwrap();
+        assert_eq!(converted.total_grains, 2500);
+        assert_eq!(converted.schedules, run.model.schedules);
+        fs::remove_dir_all(&folder).unwrap();
+    }
+}
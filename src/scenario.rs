@@ -0,0 +1,360 @@
+use crate::{
+    Boundary, Color, Lattice, Model, Rule, Schedule, ALPHA, MAX_CRITICAL, MAX_DROPS,
+    MAX_ITERATIONS, MODEL_HEIGHT, MODEL_WIDTH, SEED,
+};
+
+use serde::{Deserialize, Serialize};
+
+use std::fs;
+
+/// A Scenario describes a whole simulation - lattice, piles, colors, and snapshots - so it can be repeated and shared
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Scenario {
+    pub width: usize,
+    pub height: usize,
+    #[serde(with = "text")]
+    pub lattice: Lattice,
+    #[serde(with = "text")]
+    pub boundary_x: Boundary,
+    #[serde(with = "text")]
+    pub boundary_y: Boundary,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub critical: Option<u8>, // None collapses at one grain per neighbor
+    #[serde(with = "text")]
+    pub rule: Rule,
+    pub spread: u8,
+    pub dissipation: f32,
+    pub alpha: f32,
+    pub seed: u64,
+    pub grains: usize, // the simulation stops or pauses after this many grains - 0 for no limit
+    pub interval: usize, // grains dropped between screen updates
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub hues: Vec<String>, // untouched cells then one color per grain count - missing colors keep the defaults
+    pub snapshots: Snapshots,
+    #[serde(rename = "pile")]
+    pub piles: Vec<Pile>,
+}
+impl Default for Scenario {
+    fn default() -> Self {
+        Self {
+            width: MODEL_WIDTH,
+            height: MODEL_HEIGHT,
+            lattice: Lattice::Square,
+            boundary_x: Boundary::Open,
+            boundary_y: Boundary::Open,
+            critical: None,
+            rule: Rule::Btw,
+            spread: 0,
+            dissipation: 0.0,
+            alpha: ALPHA,
+            seed: SEED,
+            grains: MAX_ITERATIONS,
+            interval: 1_024,
+            hues: Vec::new(),
+            snapshots: Snapshots::default(),
+            piles: Vec::new(),
+        }
+    }
+}
+impl Scenario {
+    /// load() reads and checks a scenario file
+    pub fn load(filename: &str) -> Result<Self, String> {
+        let text = fs::read_to_string(filename)
+            .map_err(|why| format!("Error reading {}: {}", filename, why))?;
+        let scenario: Scenario =
+            toml::from_str(&text).map_err(|why| format!("Error in {}: {}", filename, why))?;
+        scenario.build()?;
+        Ok(scenario)
+    }
+    /// save() writes the scenario to filename as TOML
+    pub fn save(&self, filename: &str) -> Result<(), String> {
+        let text = toml::to_string(self).map_err(|why| why.to_string())?;
+        fs::write(filename, text).map_err(|why| format!("Error writing {}: {}", filename, why))
+    }
+    /// from_model() describes how model was set up - grains is the limit at which it pauses
+    pub fn from_model(model: &Model, grains: usize) -> Self {
+        let mut hues: Vec<String> = vec![model.hues.untouched.to_string()];
+        hues.extend(model.hues.grains.iter().map(Color::to_string));
+        // a paused pile stops where it was paused, and one paused before it received a grain is left out
+        let piles = (0..model.active_cells)
+            .map(|i| {
+                let (x, y) = model.idx_to_xy(model.drop_cells[i]);
+                let schedule = model.schedules[i];
+                Pile {
+                    x,
+                    y,
+                    rate: schedule.rate,
+                    start: schedule.start,
+                    stop: schedule.stop.into_iter().chain(schedule.paused).min(),
+                }
+            })
+            .filter(|pile| pile.stop.is_none_or(|stop| stop > pile.start))
+            .collect();
+        Self {
+            width: model.width,
+            height: model.height,
+            lattice: model.lattice,
+            boundary_x: model.boundary_x,
+            boundary_y: model.boundary_y,
+            critical: Some(model.critical),
+            rule: model.rule,
+            spread: model.spread,
+            dissipation: model.dissipation,
+            alpha: model.alpha,
+            seed: model.seed,
+            grains,
+            interval: model.interval,
+            hues,
+            snapshots: Snapshots::default(),
+            piles,
+        }
+    }
+    /// build() checks the scenario and creates the empty model it describes
+    pub fn build(&self) -> Result<Model, String> {
+        if self.width == 0 || self.height == 0 {
+            return Err("The lattice needs at least one cell".to_string());
+        }
+        let critical = self.critical.unwrap_or(self.lattice.neighbors());
+        if critical < self.lattice.neighbors() || critical > MAX_CRITICAL {
+            return Err(format!(
+                "A {} lattice collapses at {} to {} grains",
+                self.lattice,
+                self.lattice.neighbors(),
+                MAX_CRITICAL
+            ));
+        }
+        if !(0.0..=1.0).contains(&self.dissipation) {
+            return Err("The dissipation must be between 0 and 1".to_string());
+        }
+        let max_alpha = self.rule.max_alpha(self.lattice);
+        if !(0.0..=max_alpha).contains(&self.alpha) {
+            return Err(format!(
+                "Alpha must be between 0 and {} for the {} rule on a {} lattice",
+                max_alpha, self.rule, self.lattice
+            ));
+        }
+        if self.interval == 0 {
+            return Err("The interval must be at least one grain".to_string());
+        }
+        if self.piles.len() > MAX_DROPS {
+            return Err(format!("At most {} drop cells can be used", MAX_DROPS));
+        }
+        self.check_schedules()?;
+        let mut model = Model::new(self.width, self.height);
+        model.critical = critical;
+        model.lattice = self.lattice;
+        model.boundary_x = self.boundary_x;
+        model.boundary_y = self.boundary_y;
+        model.rule = self.rule;
+        model.spread = self.spread;
+        model.dissipation = self.dissipation;
+        model.alpha = self.alpha;
+        model.interval = self.interval;
+        model.reseed(self.seed);
+        if self.hues.len() > MAX_CRITICAL as usize + 1 {
+            return Err(format!(
+                "At most {} hues can be set - untouched cells and {} grain counts",
+                MAX_CRITICAL + 1,
+                MAX_CRITICAL
+            ));
+        }
+        let hues = [&mut model.hues.untouched]
+            .into_iter()
+            .chain(model.hues.grains.iter_mut());
+        for (hue, text) in hues.zip(&self.hues) {
+            *hue = text.parse::<Color>()?;
+        }
+        for pile in &self.piles {
+            if pile.x >= self.width || pile.y >= self.height {
+                return Err(format!(
+                    "Drop cell {},{} is beyond the {} x {} lattice",
+                    pile.x, pile.y, self.width, self.height
+                ));
+            }
+            model.drop_cells[model.active_cells] = model.xy_to_idx(pile.x, pile.y);
+            model.schedules[model.active_cells] = Schedule {
+                rate: pile.rate,
+                start: pile.start,
+                stop: pile.stop,
+                ..Schedule::default()
+            };
+            model.active_cells += 1;
+        }
+        Ok(model)
+    }
+    // check_schedules() makes sure every pile drops grains at some point and that the lattice never waits for a pile
+    // that can't start - the grain count only grows while a pile is dropping
+    fn check_schedules(&self) -> Result<(), String> {
+        let mut piles: Vec<&Pile> = self.piles.iter().collect();
+        piles.sort_by_key(|pile| pile.start);
+        let mut reach: usize = 0;
+        for pile in piles {
+            if pile.rate == 0 {
+                return Err(format!("Drop cell {},{} has a rate of 0", pile.x, pile.y));
+            }
+            if pile.stop.is_some_and(|stop| stop <= pile.start) {
+                return Err(format!(
+                    "Drop cell {},{} stops before it starts",
+                    pile.x, pile.y
+                ));
+            }
+            if pile.start > reach {
+                return Err(format!(
+                    "Drop cell {},{} starts at {} grains but every pile stops at {}",
+                    pile.x, pile.y, pile.start, reach
+                ));
+            }
+            reach = reach.max(pile.stop.unwrap_or(usize::MAX));
+        }
+        Ok(())
+    }
+}
+
+/// A Pile is a drop cell and its schedule
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Pile {
+    pub x: usize,
+    pub y: usize,
+    #[serde(default = "one")]
+    pub rate: u32, // grains dropped each turn
+    #[serde(default)]
+    pub start: usize, // total grains on the lattice before the pile starts
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stop: Option<usize>, // total grains at which the pile stops
+}
+fn one() -> u32 {
+    1
+}
+
+/// Snapshots are the grain counts at which an image of the active area is saved
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Snapshots {
+    pub every: usize,   // save an image every this many grains - 0 for none
+    pub at: Vec<usize>, // and at each of these grain counts
+    pub prefix: String, // images are named prefix_nnnnnnnn.png - "Lakhesis" when empty
+}
+impl Snapshots {
+    /// is_due() is true if a snapshot falls after grain count before and at or before grain count after
+    pub fn is_due(&self, before: usize, after: usize) -> bool {
+        (self.every > 0 && before / self.every < after / self.every)
+            || self
+                .at
+                .iter()
+                .any(|&grains| before < grains && grains <= after)
+    }
+    /// filename() names the snapshot taken at total_grains
+    pub fn filename(&self, total_grains: usize) -> String {
+        let prefix = match self.prefix.is_empty() {
+            true => "Lakhesis",
+            false => &self.prefix,
+        };
+        format!("{}_{:08}.png", prefix, total_grains)
+    }
+}
+
+// text stores lattices, boundaries, and rules by the names the command line and .lak files use
+mod text {
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    use std::fmt::Display;
+    use std::str::FromStr;
+
+    pub fn serialize<T: Display, S: Serializer>(
+        value: &T,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(value)
+    }
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+    where
+        T: FromStr<Err = String>,
+        D: Deserializer<'de>,
+    {
+        String::deserialize(deserializer)?
+            .parse::<T>()
+            .map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scenario_files() {
+        let scenario: Scenario = toml::from_str(
+            r##"
+            width = 200
+            height = 100
+            lattice = "hexagonal"
+            boundary_x = "periodic"
+            rule = "manna"
+            seed = 7
+            grains = 5000
+            hues = ["#102030", "#ff000080"]
+
+            [snapshots]
+            every = 1000
+            at = [2500]
+
+            [[pile]]
+            x = 50
+            y = 50
+            rate = 2
+
+            [[pile]]
+            x = 150
+            y = 50
+            start = 1000
+            stop = 4000
+            "##,
+        )
+        .unwrap();
+        let model = scenario.build().unwrap();
+        assert_eq!((model.width, model.height, model.critical), (200, 100, 6));
+        assert_eq!(model.boundary_x, Boundary::Periodic);
+        assert_eq!(model.boundary_y, Boundary::Open);
+        assert_eq!(model.active_cells, 2);
+        assert_eq!(model.drop_cells[1], model.xy_to_idx(150, 50));
+        assert_eq!(model.schedules[0].rate, 2);
+        assert_eq!(model.schedules[1].stop, Some(4000));
+        assert_eq!(model.hues.untouched.to_bytes(), [16, 32, 48, 255]);
+        assert_eq!(model.hues.grains[0].to_bytes(), [255, 0, 0, 128]);
+        assert!(scenario.snapshots.is_due(999, 1000));
+        assert!(scenario.snapshots.is_due(2400, 2600));
+        assert!(!scenario.snapshots.is_due(1000, 1999));
+        // a model saved as a scenario builds the same model
+        let again = Scenario::from_model(&model, scenario.grains);
+        let text = toml::to_string(&again).unwrap();
+        assert_eq!(toml::from_str::<Scenario>(&text).unwrap(), again);
+        let rebuilt = again.build().unwrap();
+        assert_eq!(rebuilt.schedules, model.schedules);
+        assert_eq!(
+            rebuilt.hues.grains[7].to_bytes(),
+            model.hues.grains[7].to_bytes()
+        );
+        assert_eq!(rebuilt.thresholds, model.thresholds);
+        // mistakes are reported rather than panicking
+        let broken = |change: fn(&mut Scenario)| {
+            let mut broken = scenario.clone();
+            change(&mut broken);
+            broken.build().is_err()
+        };
+        assert!(broken(|s| s.critical = Some(4)));
+        assert!(broken(|s| s.piles[0].x = 200));
+        assert!(broken(|s| s.piles[0].rate = 0));
+        assert!(broken(|s| s.piles[1].stop = Some(1000)));
+        assert!(broken(|s| s.piles[0].stop = Some(500)));
+        assert!(broken(|s| s.hues.push("blue".to_string())));
+        assert!(broken(|s| s.alpha = 1.5));
+        assert!(broken(|s| {
+            s.rule = Rule::Ofc;
+            s.alpha = ALPHA;
+        }));
+        assert!(toml::from_str::<Scenario>("lattice = \"pentagonal\"").is_err());
+        assert!(toml::from_str::<Scenario>("colour = \"red\"").is_err());
+    }
+}
@@ -1,27 +1,132 @@
-use crate::{Control, Model};
+use crate::{Control, Hues, Lattice, Model};
 
 use macroquad::color::colors::*;
 use macroquad::color::Color;
 use macroquad::input::*;
 use macroquad::math::*;
 use macroquad::shapes::*;
+use macroquad::texture::{draw_texture_ex, DrawTextureParams, FilterMode, Image, Texture2D};
+use macroquad::ui::root_ui;
 use macroquad::window::*;
 
-#[derive(Clone, Debug, Default)]
+use std::fmt;
+
+const MAX_ZOOM: f32 = 32.0; // screen pixels per cell when zoomed all the way in
+const ZOOM_STEP: f32 = 1.25; // each click of the mouse wheel zooms in or out by this factor
+
+/// A Frame holds the visible part of the lattice as an image and the texture it is drawn with
+struct Frame {
+    image: Image,
+    texture: Texture2D,
+    origin: (i64, i64), // lattice coordinates of the top left cell
+    columns: usize,
+    rows: usize,
+    scale: usize,  // texels per cell across
+    stride: usize, // cells per texel when zoomed out - only every stride-th cell of every stride-th row is shown
+    smooth: bool,  // blend texels - half cell shifts are less than a pixel
+    hues: Hues,
+}
+impl Frame {
+    // new() creates a blank frame - cells beyond the edge of the lattice stay transparent
+    fn new(
+        columns: usize,
+        rows: usize,
+        scale: usize,
+        stride: usize,
+        smooth: bool,
+        origin: (i64, i64),
+        hues: Hues,
+    ) -> Self {
+        let image = Image::gen_image_color(
+            (columns * scale + scale - 1) as u16,
+            rows as u16,
+            Color::new(0.0, 0.0, 0.0, 0.0),
+        );
+        let texture = Texture2D::from_image(&image);
+        texture.set_filter(match smooth {
+            true => FilterMode::Linear,
+            false => FilterMode::Nearest,
+        });
+        Self {
+            image,
+            texture,
+            origin,
+            columns,
+            rows,
+            scale,
+            stride,
+            smooth,
+            hues,
+        }
+    }
+    // paint() copies the cells of span on texel row row into the image - false if none of them are on screen
+    fn paint(&mut self, model: &Model, row: usize, span: Option<(usize, usize)>) -> bool {
+        let stride = self.stride as i64;
+        let y = self.origin.1 + row as i64 * stride;
+        let (first, last) = match span {
+            Some((first, last)) if y >= 0 && y < model.height as i64 && first <= last => {
+                (first as i64, last as i64)
+            }
+            _ => return false,
+        };
+        // texel columns of the first and last cells shown from the span
+        let first = (first - self.origin.0 + stride - 1)
+            .div_euclid(stride)
+            .max(0);
+        let last = (last.min(model.width as i64 - 1) - self.origin.0)
+            .div_euclid(stride)
+            .min(self.columns as i64 - 1);
+        if first > last {
+            return false;
+        }
+        // odd rows of a hexagonal lattice sit half a cell to the right
+        let shift = (y as usize % 2) * (self.scale - 1);
+        for column in first as usize..=last as usize {
+            let x = (self.origin.0 + (column * self.stride) as i64) as usize;
+            let hue: Color = model.cell_hue(model.xy_to_idx(x, y as usize)).into();
+            let texel = column * self.scale + shift;
+            for texel in texel..texel + self.scale {
+                self.image.set_pixel(texel as u32, row as u32, hue);
+            }
+        }
+        true
+    }
+}
+impl Drop for Frame {
+    fn drop(&mut self) {
+        self.texture.delete();
+    }
+}
+impl fmt::Debug for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Frame")
+            .field("origin", &self.origin)
+            .field("columns", &self.columns)
+            .field("rows", &self.rows)
+            .field("scale", &self.scale)
+            .field("stride", &self.stride)
+            .finish()
+    }
+}
+
+#[derive(Debug)]
 pub struct Screen {
-    pub tlx: f32,
+    pub tlx: f32, // lattice coordinates of the top left corner of the screen
     pub tly: f32,
-    pub width: f32,
+    pub width: f32, // screen size in pixels
     pub height: f32,
     pub mx: f32,
     pub my: f32,
+    pub zoom: f32,            // screen pixels per cell
+    drag: Option<(f32, f32)>, // mouse position while the lattice is dragged
+    frame: Option<Frame>,     // the lattice as last drawn
 }
 impl Screen {
     pub fn default(model: &Model) -> Self {
         let width = screen_width();
         let height = screen_height();
-        let tlx: f32 = (model.width as f32 - width).abs() / 2.0; // initial top left corner of screen
-        let tly: f32 = (model.height as f32 - height).abs() / 2.0; // centered on model
+        let tlx: f32 = (model.width as f32 - width) / 2.0; // initial top left corner of screen
+        let tly: f32 = (model.height as f32 - height) / 2.0; // centered on model - negative if model is smaller
         let (mx, my) = mouse_position();
         Self {
             tlx,
@@ -30,76 +135,93 @@ impl Screen {
             height,
             mx,
             my,
+            zoom: 1.0,
+            drag: None,
+            frame: None,
         }
     }
-    /// draw() maps visible portion of model to screen
-    pub fn draw(&self, model: &Model) {
+    /// cells_visible() returns the number of cells across and down the screen
+    pub fn cells_visible(&self) -> (f32, f32) {
+        (self.width / self.zoom, self.height / self.zoom)
+    }
+    /// draw() maps visible portion of model to screen - only the cells that changed since the last frame are repainted
+    pub fn draw(&mut self, model: &mut Model) {
+        // zoomed out, a texel shows one cell in every stride so the texture is never bigger than the screen
+        let stride = (1.0 / self.zoom).ceil().max(1.0) as usize;
+        // hexagonal cells are two texels wide so odd rows can be shifted by half a cell
+        let scale: usize = match model.lattice {
+            Lattice::Hexagonal if stride == 1 => 2,
+            _ => 1,
+        };
+        let smooth = scale > 1 && self.zoom < 2.0;
+        let (across, down) = self.cells_visible();
+        let origin = (
+            (self.tlx / stride as f32).floor() as i64 * stride as i64,
+            (self.tly / stride as f32).floor() as i64 * stride as i64,
+        );
+        let columns = ((self.tlx + across - origin.0 as f32) / stride as f32)
+            .ceil()
+            .max(1.0) as usize;
+        let rows = ((self.tly + down - origin.1 as f32) / stride as f32)
+            .ceil()
+            .max(1.0) as usize;
+        let changed = model.take_changed();
+        // panning, zooming, resizing, or new colors repaint everything
+        let current = self.frame.as_ref().is_some_and(|frame| {
+            frame.origin == origin
+                && frame.columns == columns
+                && frame.rows == rows
+                && frame.scale == scale
+                && frame.stride == stride
+                && frame.smooth == smooth
+                && frame.hues == model.hues
+        });
+        if !current {
+            self.frame = Some(Frame::new(
+                columns, rows, scale, stride, smooth, origin, model.hues,
+            ));
+        }
+        let frame = self.frame.as_mut().expect("The frame was just made");
+        let mut painted = false;
+        for row in 0..rows {
+            let span = match current {
+                true => changed
+                    .get((origin.1 + (row * stride) as i64).max(0) as usize)
+                    .copied(),
+                false => Some((0, model.width.saturating_sub(1))),
+            };
+            painted |= frame.paint(model, row, span);
+        }
+        if painted || !current {
+            frame.texture.update(&frame.image);
+        }
+        let texel = self.zoom * stride as f32 / scale as f32; // pixels per texel
+        draw_texture_ex(
+            frame.texture,
+            (origin.0 as f32 - self.tlx) * self.zoom,
+            (origin.1 as f32 - self.tly) * self.zoom,
+            WHITE,
+            DrawTextureParams {
+                dest_size: Some(vec2(
+                    frame.image.width as f32 * texel,
+                    rows as f32 * stride as f32 * self.zoom,
+                )),
+                ..Default::default()
+            },
+        );
         // draw a dot at the center of the model if visible
         let (navel_x, navel_y) = model.calc_center_xy();
-        if (navel_x as f32) >= self.tlx
-            && (navel_x as f32) <= (self.tlx + self.width)
-            && (navel_y as f32) >= self.tly
-            && (navel_y as f32) <= (self.tly + self.height)
-        {
-            let center_x: f32 = navel_x as f32 - self.tlx;
-            let center_y: f32 = navel_y as f32 - self.tly;
+        let center_x: f32 = (navel_x as f32 - self.tlx) * self.zoom;
+        let center_y: f32 = (navel_y as f32 - self.tly) * self.zoom;
+        if center_x >= 0.0 && center_x <= self.width && center_y >= 0.0 && center_y <= self.height {
             draw_rectangle(center_x, center_y, 2.0, 2.0, GRAY);
         }
-        // redrawing entire screen reduces fps - use model find_extent() to only update active part of model
-        let mut render: bool = true;
-        let (left, top, left_plus, top_plus) = model.find_extent();
-        if (left + left_plus as u32) < self.tlx.trunc() as u32
-            || left > (self.tlx + self.width).trunc() as u32
-            || (top + top_plus as u32) < self.tly.trunc() as u32
-            || top > (self.tly + self.height).trunc() as u32
-        {
-            render = false;
-        }
-        if render {
-            let mut xstart: usize = 0;
-            let mut xstop: usize = self.width.trunc() as usize;
-            let mut ystart: usize = 0;
-            let mut ystop: usize = self.height.trunc() as usize;
-            if left > self.tlx.trunc() as u32 {
-                xstart = (left - self.tlx.trunc() as u32) as usize
-            };
-            if (left + left_plus as u32) < (self.tlx + self.width).trunc() as u32 {
-                xstop = ((left + left_plus as u32) - self.tlx.trunc() as u32) as usize
-            };
-            if top > self.tly.trunc() as u32 {
-                ystart = (top - self.tly.trunc() as u32) as usize
-            };
-            if (top + top_plus as u32) < (self.tly + self.height).trunc() as u32 {
-                ystop = ((top + top_plus as u32) - self.tly.trunc() as u32) as usize
-            };
-
-            for i in ystart..ystop {
-                for j in xstart..xstop {
-                    let idx = model
-                        .xy_to_idx(j + self.tlx.trunc() as usize, i + self.tly.trunc() as usize);
-                    let mut pixel_color: Color = model.hues.untouched;
-                    match model.cells[idx].grains {
-                        0 => {
-                            if model.cells[i].borged {
-                                // untouched pixels are left transparent black
-                                pixel_color = model.hues.zero_grains;
-                            }
-                        }
-                        1 => pixel_color = model.hues.one_grain,
-                        2 => pixel_color = model.hues.two_grains,
-                        3 => pixel_color = model.hues.three_grains,
-                        _ => pixel_color = model.hues.four_grains,
-                    };
-                    draw_rectangle(j as f32, i as f32, 1.0, 1.0, pixel_color);
-                }
-            }
-        }
     }
     /// magnify_box() magnifies a 32 by 32 pixel area within the image
     pub fn magnify_box(&self, model: &Model) {
         let top_left_x = (self.mx - 16.0).trunc() as usize;
         let top_left_y = (self.my - 16.0).trunc() as usize;
-        let mut bg: Color = model.hues.untouched;
+        let mut bg: Color = model.hues.untouched.into();
         if bg.a <= 0.5 {
             bg.a = 1.0;
         };
@@ -114,35 +236,43 @@ impl Screen {
         let curs: Color = WHITE;
         for i in 0..32 {
             for j in 0..32 {
-                let idx = model.xy_to_idx(
-                    j + self.tlx.trunc() as usize + top_left_x,
-                    i + self.tly.trunc() as usize + top_left_y,
-                );
-                let mut pixel_color: Color = bg; // background color instead of untouched so unmagnified image is blocked
-                match model.cells[idx].grains {
-                    0 => {
-                        if model.cells[idx].borged {
-                            pixel_color = model.hues.zero_grains;
-                        }
-                    }
-                    1 => {
-                        pixel_color = model.hues.one_grain;
-                    }
-                    2 => {
-                        pixel_color = model.hues.two_grains;
+                let (x, y) = match self.lattice_xy(
+                    model,
+                    (top_left_x + j) as f32,
+                    (top_left_y + i) as f32,
+                ) {
+                    Some(xy) => xy,
+                    None => continue, // beyond the edge of the lattice
+                };
+                let idx = model.xy_to_idx(x, y);
+                // background color instead of untouched so unmagnified image is blocked
+                let pixel_color: Color = match model.cells[idx].borged {
+                    true => model.cell_hue(idx).into(),
+                    false => bg,
+                };
+                let px = (self.width - 150.0) + (j * 4) as f32;
+                let py = (self.height - 150.0) + (i * 4) as f32;
+                match model.lattice {
+                    // hexagonal rows are offset by half a cell
+                    Lattice::Hexagonal if y % 2 == 1 => {
+                        draw_rectangle(px + 2.0, py, 4.0, 4.0, pixel_color);
                     }
-                    3 => {
-                        pixel_color = model.hues.three_grains;
+                    // triangles alternate pointing up and down and overlap their neighbors by half a base
+                    Lattice::Triangular => {
+                        let (apex, base) = if (x + y).is_multiple_of(2) {
+                            (py, py + 4.0)
+                        } else {
+                            (py + 4.0, py)
+                        };
+                        draw_triangle(
+                            vec2(px - 2.0, base),
+                            vec2(px + 6.0, base),
+                            vec2(px + 2.0, apex),
+                            pixel_color,
+                        );
                     }
-                    _ => pixel_color = model.hues.four_grains,
-                };
-                draw_rectangle(
-                    (self.width - 150.0) + (j * 4) as f32,
-                    (self.height - 150.0) + (i * 4) as f32,
-                    4.0,
-                    4.0,
-                    pixel_color,
-                );
+                    _ => draw_rectangle(px, py, 4.0, 4.0, pixel_color),
+                }
             }
         }
         draw_rectangle_lines(
@@ -164,6 +294,93 @@ impl Screen {
             curs,
         );
     }
+    /// lattice_xy() converts a screen position into lattice (x, y) coordinates - None if off the lattice
+    pub fn lattice_xy(&self, model: &Model, sx: f32, sy: f32) -> Option<(usize, usize)> {
+        let y = (self.tly + sy / self.zoom).floor();
+        // odd rows of a hexagonal lattice are drawn half a cell to the right
+        let shift = match model.lattice {
+            Lattice::Hexagonal if y >= 0.0 && y as usize % 2 == 1 => 0.5,
+            _ => 0.0,
+        };
+        let x = (self.tlx + sx / self.zoom - shift).floor();
+        if x < 0.0 || y < 0.0 || x >= model.width as f32 || y >= model.height as f32 {
+            None
+        } else {
+            Some((x as usize, y as usize))
+        }
+    }
+    /// screen_xy() converts lattice (x, y) coordinates into the screen position of the top left corner of the cell
+    pub fn screen_xy(&self, model: &Model, x: usize, y: usize) -> (f32, f32) {
+        let shift = match model.lattice {
+            Lattice::Hexagonal if y % 2 == 1 => 0.5,
+            _ => 0.0,
+        };
+        (
+            (x as f32 + shift - self.tlx) * self.zoom,
+            (y as f32 - self.tly) * self.zoom,
+        )
+    }
+    /// center() positions the screen over the midpoint of the lattice
+    pub fn center(&mut self, model: &Model) {
+        let (across, down) = self.cells_visible();
+        self.tlx = (model.width as f32 - across) / 2.0;
+        self.tly = (model.height as f32 - down) / 2.0;
+    }
+    /// pan() moves the screen by (dx, dy) pixels without leaving the lattice
+    pub fn pan(&mut self, model: &Model, dx: f32, dy: f32) {
+        let (across, down) = self.cells_visible();
+        // a lattice smaller than the screen stays centered
+        if model.width as f32 > across {
+            self.tlx = (self.tlx + dx / self.zoom).clamp(0.0, model.width as f32 - across);
+        } else {
+            self.tlx = (model.width as f32 - across) / 2.0;
+        }
+        if model.height as f32 > down {
+            self.tly = (self.tly + dy / self.zoom).clamp(0.0, model.height as f32 - down);
+        } else {
+            self.tly = (model.height as f32 - down) / 2.0;
+        }
+    }
+    /// fit_zoom() returns the zoom that shows the whole lattice - never more than one pixel per cell
+    pub fn fit_zoom(&self, model: &Model) -> f32 {
+        (self.width / model.width as f32)
+            .min(self.height / model.height as f32)
+            .min(1.0)
+    }
+    /// zoom_at() changes the zoom keeping the cell under screen position (sx, sy) where it is
+    pub fn zoom_at(&mut self, model: &Model, zoom: f32, sx: f32, sy: f32) {
+        let (x, y) = (self.tlx + sx / self.zoom, self.tly + sy / self.zoom);
+        self.zoom = zoom.clamp(self.fit_zoom(model), MAX_ZOOM);
+        self.tlx = x - sx / self.zoom;
+        self.tly = y - sy / self.zoom;
+        self.pan(model, 0.0, 0.0);
+    }
+    /// navigate() zooms with the mouse wheel and pans when the lattice is dragged
+    pub fn navigate(&mut self, model: &Model, control: &Control) {
+        let (mx, my) = mouse_position();
+        // the mouse belongs to the control panel and the other windows while it is over them
+        let over_ui = root_ui().is_mouse_over(vec2(mx, my));
+        let (_, wheel) = mouse_wheel();
+        if wheel != 0.0 && !over_ui {
+            let zoom = self.zoom * ZOOM_STEP.powf(wheel.signum());
+            self.zoom_at(model, zoom, mx, my);
+        }
+        // a click that places a new sandpile or picks one up doesn't drag the lattice
+        if is_mouse_button_pressed(MouseButton::Left)
+            && !over_ui
+            && !control.add
+            && control.dragging.is_none()
+        {
+            self.drag = Some((mx, my));
+        }
+        if !is_mouse_button_down(MouseButton::Left) {
+            self.drag = None;
+        }
+        if let Some((x, y)) = self.drag {
+            self.pan(model, x - mx, y - my);
+            self.drag = Some((mx, my));
+        }
+    }
     /// crosshairs() draws the lakhesis cursor
     pub fn crosshairs(&mut self, model: &Model, control: &Control) {
         // get current mouse position and limit extent if magnify is on
@@ -193,6 +410,13 @@ impl Screen {
                 draw_line(self.mx - 10.0, self.my, self.mx + 10.0, self.my, 1.0, curs);
                 draw_line(self.mx, self.my - 10.0, self.mx, self.my + 10.0, 1.0, curs);
             }
+            // zoomed in far enough to see cells, outline the one under the cursor
+            if self.zoom >= 4.0 {
+                if let Some((x, y)) = self.lattice_xy(model, self.mx, self.my) {
+                    let (cx, cy) = self.screen_xy(model, x, y);
+                    draw_rectangle_lines(cx, cy, self.zoom, self.zoom, 1.0, curs);
+                }
+            }
         }
     }
 }
@@ -0,0 +1,220 @@
+use crate::color::BLANK;
+use crate::{Color, Hues, Model, Raster, UNFED};
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+
+/// A Seam is the band of cells where the territories of two drop cells meet - a territory is the cells a drop cell
+/// fed most, see Model::track_sources()
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Seam {
+    pub piles: (usize, usize), // the drop cells on either side - the lower first
+    pub cells: Vec<(usize, usize)>, // each cell that borders the other drop cell's territory
+    pub center: (f32, f32),    // mean position of the cells
+    pub length: f32,           // extent of the cells along the main axis
+    pub width: f32,            // average number of cells across the main axis
+    pub orientation: f32,      // angle of the main axis in degrees - 0 runs along x and 90 along y
+    pub polyline: Vec<(f32, f32)>, // middle of the seam at each step along the main axis
+}
+impl Seam {
+    // measure() finds the main axis of the cells and measures the seam along and across it
+    fn measure(piles: (usize, usize), cells: Vec<(usize, usize)>) -> Self {
+        let count = cells.len() as f32;
+        let cx = cells.iter().map(|&(x, _)| x as f32).sum::<f32>() / count;
+        let cy = cells.iter().map(|&(_, y)| y as f32).sum::<f32>() / count;
+        let (mut sxx, mut syy, mut sxy) = (0.0, 0.0, 0.0);
+        for &(x, y) in cells.iter() {
+            let (dx, dy) = (x as f32 - cx, y as f32 - cy);
+            sxx += dx * dx;
+            syy += dy * dy;
+            sxy += dx * dy;
+        }
+        // the main axis is the direction the cells spread furthest in
+        let angle = 0.5 * (2.0 * sxy).atan2(sxx - syy);
+        let (cos, sin) = (angle.cos(), angle.sin());
+        let along = |&(x, y): &(usize, usize)| (x as f32 - cx) * cos + (y as f32 - cy) * sin;
+        let across = |&(x, y): &(usize, usize)| (y as f32 - cy) * cos - (x as f32 - cx) * sin;
+        let first = cells.iter().map(along).fold(f32::MAX, f32::min);
+        let last = cells.iter().map(along).fold(f32::MIN, f32::max);
+        let length = last - first + 1.0;
+        // the middle of the cells in each one cell step along the main axis
+        let mut steps: BTreeMap<usize, (f32, usize)> = BTreeMap::new();
+        for cell in cells.iter() {
+            let step = steps
+                .entry((along(cell) - first).round() as usize)
+                .or_insert((0.0, 0));
+            step.0 += across(cell);
+            step.1 += 1;
+        }
+        let polyline = steps
+            .iter()
+            .map(|(&step, &(offset, count))| {
+                let t = first + step as f32;
+                let s = offset / count as f32;
+                (cx + t * cos - s * sin, cy + t * sin + s * cos)
+            })
+            .collect();
+        Self {
+            piles,
+            center: (cx, cy),
+            length,
+            width: count / length,
+            orientation: angle.to_degrees().rem_euclid(180.0),
+            polyline,
+            cells,
+        }
+    }
+}
+
+/// Seams are every seam of a model and the size of its lattice
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Seams {
+    pub seams: Vec<Seam>, // ordered by the drop cells on either side
+    pub width: usize,
+    pub height: usize,
+}
+impl Seams {
+    /// find() returns the seam between every pair of drop cells whose territories touch - there are none unless the
+    /// model tracks sources
+    pub fn find(model: &Model) -> Self {
+        let mut pairs: BTreeMap<(usize, usize), Vec<(usize, usize)>> = BTreeMap::new();
+        for (idx, &(source, _)) in model.sources.iter().enumerate() {
+            if source == UNFED {
+                continue;
+            }
+            let (adjacent, count) = model.neighbors(idx);
+            let mut others: Vec<u8> = adjacent[..count]
+                .iter()
+                .flatten()
+                .map(|&nidx| model.sources[nidx].0)
+                .filter(|&other| other != UNFED && other != source)
+                .collect();
+            others.sort_unstable();
+            others.dedup();
+            // a cell where three territories meet is part of two seams
+            for other in others {
+                let pair = (source.min(other) as usize, source.max(other) as usize);
+                pairs.entry(pair).or_default().push(model.idx_to_xy(idx));
+            }
+        }
+        Self {
+            seams: pairs
+                .into_iter()
+                .map(|(piles, cells)| Seam::measure(piles, cells))
+                .collect(),
+            width: model.width,
+            height: model.height,
+        }
+    }
+    /// to_csv() lists each seam's drop cells, size, and direction - one row per seam
+    pub fn to_csv(&self) -> String {
+        let mut csv =
+            "pile_a,pile_b,cells,length,width,orientation,center_x,center_y\n".to_string();
+        for seam in self.seams.iter() {
+            csv.push_str(&format!(
+                "{},{},{},{:.1},{:.2},{:.1},{:.1},{:.1}\n",
+                seam.piles.0,
+                seam.piles.1,
+                seam.cells.len(),
+                seam.length,
+                seam.width,
+                seam.orientation,
+                seam.center.0,
+                seam.center.1
+            ));
+        }
+        csv
+    }
+    /// polylines_csv() lists the points along the middle of each seam - one row per point
+    pub fn polylines_csv(&self) -> String {
+        let mut csv = "pile_a,pile_b,x,y\n".to_string();
+        for seam in self.seams.iter() {
+            for &(x, y) in seam.polyline.iter() {
+                csv.push_str(&format!(
+                    "{},{},{:.2},{:.2}\n",
+                    seam.piles.0, seam.piles.1, x, y
+                ));
+            }
+        }
+        csv
+    }
+    /// save() writes the seams to name.csv, the middle of each to name_lines.csv, and the mask to name.png
+    pub fn save(&self, name: &str) -> io::Result<()> {
+        fs::write(format!("{}.csv", name), self.to_csv())?;
+        fs::write(format!("{}_lines.csv", name), self.polylines_csv())?;
+        self.mask().export_png(&format!("{}.png", name))
+    }
+    /// mask() draws the seam cells on a blank raster the size of the lattice - each seam in a mix of the colors of
+    /// the drop cells on either side
+    pub fn mask(&self) -> Raster {
+        let mut mask = Raster::new(self.width, self.height, BLANK);
+        for seam in self.seams.iter() {
+            let (a, b) = (
+                Hues::source_hue(seam.piles.0),
+                Hues::source_hue(seam.piles.1),
+            );
+            let hue = Color::new((a.r + b.r) / 2.0, (a.g + b.g) / 2.0, (a.b + b.b) / 2.0, 1.0);
+            for &(x, y) in seam.cells.iter() {
+                mask.set_pixel(x, y, hue);
+            }
+        }
+        mask
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seams_between_piles() {
+        let mut model = Model::new(60, 40);
+        model.active_cells = 2;
+        model.drop_cells[0] = model.xy_to_idx(18, 20);
+        model.drop_cells[1] = model.xy_to_idx(42, 20);
+        // without sources there are no territories
+        assert!(Seams::find(&model).seams.is_empty());
+        model.track_sources(true);
+        for _ in 0..3_000 {
+            model.drop_grain();
+        }
+        let seams = Seams::find(&model);
+        assert_eq!(seams.seams.len(), 1);
+        let seam = &seams.seams[0];
+        assert_eq!(seam.piles, (0, 1));
+        // piles side by side meet along a vertical seam halfway between them
+        assert!(
+            (seam.orientation - 90.0).abs() < 10.0,
+            "{}",
+            seam.orientation
+        );
+        assert!((seam.center.0 - 30.0).abs() < 2.0, "{:?}", seam.center);
+        assert!(seam.length > 10.0 && seam.width >= 1.0);
+        assert!(seam.cells.iter().all(|&(x, _)| (25..=35).contains(&x)));
+        assert!(seam.polyline.len() as f32 <= seam.length);
+        // one row per seam and one per point, and a mask pixel for every seam cell
+        assert_eq!(seams.to_csv().lines().count(), 2);
+        assert_eq!(
+            seams.polylines_csv().lines().count(),
+            1 + seam.polyline.len()
+        );
+        let mask = seams.mask();
+        assert_eq!((mask.width, mask.height), (60, 40));
+        let (x, y) = seam.cells[0];
+        assert_ne!(mask.get_pixel(x, y), BLANK.to_bytes());
+        assert_eq!(mask.get_pixel(0, 0), BLANK.to_bytes());
+        let name = std::env::temp_dir()
+            .join(format!("lakhesis_seams_{}", std::process::id()))
+            .display()
+            .to_string();
+        seams.save(&name).unwrap();
+        for file in [
+            format!("{}.csv", name),
+            format!("{}_lines.csv", name),
+            format!("{}.png", name),
+        ] {
+            fs::remove_file(file).unwrap();
+        }
+    }
+}
@@ -0,0 +1,326 @@
+use crate::Model;
+
+use std::collections::BTreeMap;
+
+pub const FIT_MIN: usize = 10; // smallest avalanche size and duration included in the power law fits
+pub const GROWTH_MIN: f32 = 4.0; // smallest radius included in the growth exponent fit
+
+/// An Avalanche summarizes the collapses caused by dropping a single sand grain
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Avalanche {
+    pub topples: usize,  // number of collapses - the usual measure of avalanche size
+    pub area: usize,     // number of distinct cells that collapsed at least once
+    pub lost: usize,     // grains that fell off the 'table' or were dissipated
+    pub duration: usize, // number of toppling generations - cells that collapse in parallel count once
+    pub radius: f32,     // distance from the drop cell to the furthest cell that collapsed
+    pub min_x: usize,    // bounding box of the cells that collapsed
+    pub min_y: usize,
+    pub max_x: usize,
+    pub max_y: usize,
+    pub touched: usize, // cells that received a grain for the first time
+}
+impl Avalanche {
+    /// start() creates an empty avalanche whose bounding box is the drop cell
+    pub fn start(x: usize, y: usize) -> Self {
+        Self {
+            min_x: x,
+            min_y: y,
+            max_x: x,
+            max_y: y,
+            ..Default::default()
+        }
+    }
+}
+
+/// A Histogram counts how often each avalanche size, area, duration, and loss has occurred
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Histogram {
+    pub grains: usize, // number of grains dropped since the histogram was started
+    pub topples: BTreeMap<usize, usize>,
+    pub area: BTreeMap<usize, usize>,
+    pub duration: BTreeMap<usize, usize>,
+    pub lost: BTreeMap<usize, usize>,
+}
+impl Histogram {
+    /// record() adds an avalanche to the running counts - grains that caused no collapse only count as grains
+    pub fn record(&mut self, avalanche: &Avalanche) {
+        self.grains += 1;
+        if avalanche.topples > 0 {
+            *self.topples.entry(avalanche.topples).or_insert(0) += 1;
+            *self.area.entry(avalanche.area).or_insert(0) += 1;
+            *self.duration.entry(avalanche.duration).or_insert(0) += 1;
+            *self.lost.entry(avalanche.lost).or_insert(0) += 1;
+        }
+    }
+    /// avalanches() returns the number of grains that caused at least one collapse
+    pub fn avalanches(&self) -> usize {
+        self.topples.values().sum()
+    }
+    /// largest() returns the largest avalanche size recorded
+    pub fn largest(&self) -> usize {
+        self.topples.keys().next_back().copied().unwrap_or(0)
+    }
+    /// to_csv() lists how often each value occurred as size, area, duration, and loss - one row per value
+    pub fn to_csv(&self) -> String {
+        let mut values: Vec<usize> = self
+            .topples
+            .keys()
+            .chain(self.area.keys())
+            .chain(self.duration.keys())
+            .chain(self.lost.keys())
+            .copied()
+            .collect();
+        values.sort_unstable();
+        values.dedup();
+        let mut csv = String::from("value,topples,area,duration,lost\n");
+        for value in values {
+            let count = |counts: &BTreeMap<usize, usize>| counts.get(&value).copied().unwrap_or(0);
+            csv.push_str(&format!(
+                "{},{},{},{},{}\n",
+                value,
+                count(&self.topples),
+                count(&self.area),
+                count(&self.duration),
+                count(&self.lost)
+            ));
+        }
+        csv
+    }
+    /// mean() returns the average of a histogram's values weighted by their counts
+    pub fn mean(counts: &BTreeMap<usize, usize>) -> f64 {
+        let n: usize = counts.values().sum();
+        if n == 0 {
+            return 0.0;
+        }
+        let total: f64 = counts
+            .iter()
+            .map(|(value, count)| *value as f64 * *count as f64)
+            .sum();
+        total / n as f64
+    }
+    /// log_bins() groups a histogram into bins that double in width and returns (center, density) pairs
+    pub fn log_bins(counts: &BTreeMap<usize, usize>) -> Vec<(f64, f64)> {
+        let total: usize = counts.range(1..).map(|(_, count)| count).sum();
+        let largest: usize = counts.keys().next_back().copied().unwrap_or(0);
+        let mut bins: Vec<(f64, f64)> = Vec::new();
+        let mut lower: usize = 1;
+        while total > 0 && lower <= largest {
+            let upper = lower * 2;
+            let count: usize = counts.range(lower..upper).map(|(_, count)| count).sum();
+            if count > 0 {
+                // geometric center of the bin and the probability density within it
+                let center = ((lower * (upper - 1)) as f64).sqrt();
+                let density = count as f64 / (total as f64 * (upper - lower) as f64);
+                bins.push((center, density));
+            }
+            lower = upper;
+        }
+        bins
+    }
+}
+
+/// A PowerLaw is a maximum likelihood fit of P(x) ~ x^-exponent to the values at or above xmin
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PowerLaw {
+    pub exponent: f64,
+    pub error: f64,     // standard error of the exponent
+    pub cutoff: f64, // moment ratio <x^2>/<x> - grows with the cutoff imposed by the finite lattice
+    pub xmin: usize, // smallest value included in the fit
+    pub samples: usize, // number of values at or above xmin
+}
+impl PowerLaw {
+    /// fit() estimates the exponent using the discrete approximation of Clauset, Shalizi, and Newman (2009)
+    pub fn fit(counts: &BTreeMap<usize, usize>, xmin: usize) -> Option<Self> {
+        let xmin = xmin.max(1);
+        let offset = xmin as f64 - 0.5;
+        let mut samples: usize = 0;
+        let mut log_sum: f64 = 0.0;
+        let mut first: f64 = 0.0;
+        let mut second: f64 = 0.0;
+        for (&value, &count) in counts.range(xmin..) {
+            let (value, weight) = (value as f64, count as f64);
+            samples += count;
+            log_sum += weight * (value / offset).ln();
+            first += weight * value;
+            second += weight * value * value;
+        }
+        if samples < 2 || log_sum <= 0.0 {
+            return None;
+        }
+        let exponent = 1.0 + samples as f64 / log_sum;
+        Some(Self {
+            exponent,
+            error: (exponent - 1.0) / (samples as f64).sqrt(),
+            cutoff: second / first,
+            xmin,
+            samples,
+        })
+    }
+}
+
+/// A Growth follows the pile fed by one drop cell - its avalanches measure how far it reaches
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Growth {
+    pub grains: usize, // grains dropped on the drop cell
+    pub area: usize,   // cells its avalanches touched first
+    pub radius: f32,   // distance from the drop cell to the furthest cell its avalanches collapsed
+    pub min_x: usize,  // bounding box of the cells its avalanches collapsed
+    pub min_y: usize,
+    pub max_x: usize,
+    pub max_y: usize,
+    pub curve: Vec<(usize, f32)>, // grains and radius each time the radius reached another whole cell
+}
+impl Growth {
+    /// record() adds the avalanche caused by one of the drop cell's grains
+    pub fn record(&mut self, avalanche: &Avalanche) {
+        if self.grains == 0 {
+            (self.min_x, self.min_y) = (avalanche.min_x, avalanche.min_y);
+            (self.max_x, self.max_y) = (avalanche.max_x, avalanche.max_y);
+        }
+        self.grains += 1;
+        self.area += avalanche.touched;
+        self.min_x = self.min_x.min(avalanche.min_x);
+        self.min_y = self.min_y.min(avalanche.min_y);
+        self.max_x = self.max_x.max(avalanche.max_x);
+        self.max_y = self.max_y.max(avalanche.max_y);
+        if avalanche.radius > self.radius {
+            let reached = self.curve.last().map_or(0.0, |&(_, radius)| radius.floor());
+            self.radius = avalanche.radius;
+            if self.radius.floor() > reached {
+                self.curve.push((self.grains, self.radius));
+            }
+        }
+    }
+    /// exponent() fits radius ~ grains^exponent to the curve by least squares on log-log axes - a lone pile grows
+    /// as the square root of its grains, so close to 0.5
+    pub fn exponent(&self) -> Option<f64> {
+        // the first few cells are too coarse to follow the law
+        let points: Vec<(f64, f64)> = self
+            .curve
+            .iter()
+            .filter(|&&(_, radius)| radius >= GROWTH_MIN)
+            .map(|&(grains, radius)| ((grains as f64).ln(), (radius as f64).ln()))
+            .collect();
+        if points.len() < 3 {
+            return None;
+        }
+        let n = points.len() as f64;
+        let mx = points.iter().map(|(x, _)| x).sum::<f64>() / n;
+        let my = points.iter().map(|(_, y)| y).sum::<f64>() / n;
+        let sxx: f64 = points.iter().map(|(x, _)| (x - mx) * (x - mx)).sum();
+        let sxy: f64 = points.iter().map(|(x, y)| (x - mx) * (y - my)).sum();
+        (sxx > 0.0).then(|| sxy / sxx)
+    }
+    /// to_csv() lists each drop cell's grains, area, radius, bounding box, and growth exponent - one row per drop cell
+    pub fn to_csv(model: &Model) -> String {
+        let mut csv = "pile,x,y,grains,area,radius,min_x,min_y,max_x,max_y,exponent\n".to_string();
+        for (i, growth) in model.growth.iter().enumerate() {
+            let (x, y) = model.idx_to_xy(model.drop_cells[i]);
+            csv.push_str(&format!(
+                "{},{},{},{},{},{:.2},{},{},{},{},{}\n",
+                i,
+                x,
+                y,
+                growth.grains,
+                growth.area,
+                growth.radius,
+                growth.min_x,
+                growth.min_y,
+                growth.max_x,
+                growth.max_y,
+                growth
+                    .exponent()
+                    .map_or(String::new(), |exponent| format!("{:.3}", exponent))
+            ));
+        }
+        csv
+    }
+    /// curves_csv() lists the radius of each drop cell's pile as its grains were added - one row per point
+    pub fn curves_csv(model: &Model) -> String {
+        let mut csv = "pile,grains,radius\n".to_string();
+        for (i, growth) in model.growth.iter().enumerate() {
+            for &(grains, radius) in growth.curve.iter() {
+                csv.push_str(&format!("{},{},{:.2}\n", i, grains, radius));
+            }
+        }
+        csv
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn power_law_fit() {
+        // counts following x^-2.5 should give back an exponent close to 2.5
+        let mut counts: BTreeMap<usize, usize> = BTreeMap::new();
+        for x in 1..100_000_usize {
+            let count = (1.0e12 * (x as f64).powf(-2.5)).round() as usize;
+            if count > 0 {
+                counts.insert(x, count);
+            }
+        }
+        let fit = PowerLaw::fit(&counts, 10).unwrap();
+        assert!((fit.exponent - 2.5).abs() < 0.02, "{:?}", fit);
+        assert!(fit.cutoff > 10.0);
+        assert!(PowerLaw::fit(&BTreeMap::new(), 1).is_none());
+        // densities times bin widths add up to one
+        let bins = Histogram::log_bins(&counts);
+        let mut total: f64 = 0.0;
+        let mut lower: f64 = 1.0;
+        for (center, density) in bins {
+            assert!(center >= lower && center < lower * 2.0);
+            total += density * lower;
+            lower *= 2.0;
+        }
+        assert!((total - 1.0).abs() < 1.0e-9);
+    }
+
+    #[test]
+    fn pile_growth() {
+        let mut model = Model::new(120, 120);
+        model.active_cells = 2;
+        model.drop_cells[0] = model.xy_to_idx(60, 60);
+        model.drop_cells[1] = model.xy_to_idx(5, 5);
+        for _ in 0..16_000 {
+            model.add_grain();
+        }
+        assert_eq!(model.growth.len(), 1);
+        let growth = &model.growth[0];
+        assert_eq!(growth.grains, 16_000);
+        // every cell touched so far was touched by the only pile
+        assert_eq!(
+            growth.area,
+            model.cells.iter().filter(|cell| cell.borged).count()
+        );
+        // a lone pile grows symmetrically and its radius follows the square root of its grains
+        assert_eq!(60 - growth.min_x, growth.max_x - 60);
+        assert_eq!((growth.min_x, growth.max_x), (growth.min_y, growth.max_y));
+        assert!(growth
+            .curve
+            .windows(2)
+            .all(|pair| pair[0].0 < pair[1].0 && pair[0].1.floor() < pair[1].1.floor()));
+        assert_eq!(
+            growth.curve.last().unwrap().1.floor(),
+            growth.radius.floor()
+        );
+        let exponent = growth.exponent().unwrap();
+        assert!((exponent - 0.5).abs() < 0.08, "{}", exponent);
+        // one row per pile and one per point
+        assert!(Growth::to_csv(&model).starts_with("pile,x,y,"));
+        assert!(Growth::to_csv(&model).contains("\n0,60,60,16000,"));
+        assert_eq!(
+            Growth::curves_csv(&model).lines().count(),
+            1 + growth.curve.len()
+        );
+        // the second pile starts from nothing and leaves with its drop cell
+        model.ac = 1;
+        model.add_grain();
+        assert_eq!(model.growth[1].grains, 1);
+        assert_eq!(model.growth[1].curve, Vec::new());
+        model.remove_drop_cell(0);
+        assert_eq!(model.growth.len(), 1);
+        assert_eq!(model.growth[0].grains, 1);
+    }
+}
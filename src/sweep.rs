@@ -0,0 +1,335 @@
+use crate::runner::value;
+use crate::{Histogram, PowerLaw, Run, FIT_MIN};
+
+use std::f64::consts::PI;
+use std::fmt::Write;
+use std::fs;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Instant;
+
+pub const SWEEP_GRAINS: usize = 100_000; // grains per run unless --grains is given
+const SWEEP_HEADER: &str = "run,piles,separation,size,critical,grains,lost,avalanches,largest,mean_size,mean_duration,size_exponent,duration_exponent,size_cutoff,image,seconds,note";
+
+/// A Trial is one combination of the swept parameters
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Trial {
+    pub piles: usize,
+    pub separation: usize,    // distance in cells between neighboring piles
+    pub size: usize,          // width and height of the lattice
+    pub critical: Option<u8>, // None leaves the lattice's own threshold
+}
+impl Trial {
+    /// drops() places the piles on the corners of a regular polygon centered on the lattice
+    pub fn drops(&self) -> Result<Vec<(usize, usize)>, String> {
+        let center = (self.size / 2) as f64;
+        // neighboring corners are separation apart - a single pile sits on the center
+        let radius = match self.piles {
+            0 | 1 => 0.0,
+            n => self.separation as f64 / (2.0 * (PI / n as f64).sin()),
+        };
+        let mut drops: Vec<(usize, usize)> = Vec::with_capacity(self.piles);
+        for pile in 0..self.piles {
+            let angle = 2.0 * PI * pile as f64 / self.piles as f64;
+            let x = (center + radius * angle.cos()).round();
+            let y = (center + radius * angle.sin()).round();
+            if x < 0.0 || y < 0.0 || x >= self.size as f64 || y >= self.size as f64 {
+                return Err(format!(
+                    "{} piles {} apart don't fit on a {} x {} lattice",
+                    self.piles, self.separation, self.size, self.size
+                ));
+            }
+            drops.push((x as usize, y as usize));
+        }
+        Ok(drops)
+    }
+}
+
+/// A Sweep runs every combination of pile count, separation, lattice size, and threshold
+#[derive(Clone, Debug)]
+pub struct Sweep {
+    pub piles: Vec<usize>,
+    pub separations: Vec<usize>,
+    pub sizes: Vec<usize>,
+    pub criticals: Vec<u8>,   // empty leaves each lattice's own threshold
+    pub options: Vec<String>, // run options shared by every trial
+    pub csv: String,
+    pub images: Option<String>, // folder for one PNG per trial
+    pub threads: usize,
+}
+impl Sweep {
+    /// parse() builds a sweep from the arguments that follow "sweep" - anything it doesn't know is passed on to each run
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        let mut sweep = Self {
+            piles: vec![2],
+            separations: vec![100],
+            sizes: vec![500],
+            criticals: Vec::new(),
+            options: vec!["--grains".to_string(), SWEEP_GRAINS.to_string()],
+            csv: "sweep.csv".to_string(),
+            images: None,
+            threads: thread::available_parallelism().map_or(1, |n| n.get()),
+        };
+        let mut args = args.iter().peekable();
+        while let Some(flag) = args.next() {
+            let mut next = || args.next().ok_or(format!("{} needs a value", flag));
+            match flag.as_str() {
+                "--piles" => sweep.piles = range(flag, next()?)?,
+                "--separation" => sweep.separations = range(flag, next()?)?,
+                "--size" => sweep.sizes = range(flag, next()?)?,
+                "--critical" => {
+                    sweep.criticals = range(flag, next()?)?
+                        .into_iter()
+                        .map(|critical| u8::try_from(critical).unwrap_or(u8::MAX))
+                        .collect()
+                }
+                "--csv" => sweep.csv = next()?.clone(),
+                "--images" => sweep.images = Some(next()?.clone()),
+                "--threads" => sweep.threads = value(flag, next()?)?,
+                "--width" | "--height" | "--drops" | "--scenario" | "--replay" | "--out"
+                | "--png" | "--stats" | "--journal" | "--growth" | "--growth-curve" | "--seams"
+                | "--seam-lines" | "--seam-mask" | "--patterns" => {
+                    return Err(format!("{} is set by the sweep for every run", flag))
+                }
+                _ => {
+                    sweep.options.push(flag.clone());
+                    if let Some(option) = args.next_if(|option| !option.starts_with("--")) {
+                        sweep.options.push(option.clone());
+                    }
+                }
+            }
+        }
+        if sweep.threads == 0 {
+            return Err("A sweep needs at least one thread".to_string());
+        }
+        // catch mistakes in the shared options before any time is spent running
+        Run::parse(&sweep.options)?;
+        Ok(sweep)
+    }
+    /// trials() lists every combination of the swept parameters
+    pub fn trials(&self) -> Vec<Trial> {
+        let criticals: Vec<Option<u8>> = match self.criticals.is_empty() {
+            true => vec![None],
+            false => self.criticals.iter().map(|&c| Some(c)).collect(),
+        };
+        let mut trials: Vec<Trial> = Vec::new();
+        for &piles in &self.piles {
+            for &separation in &self.separations {
+                for &size in &self.sizes {
+                    for &critical in &criticals {
+                        trials.push(Trial {
+                            piles,
+                            separation,
+                            size,
+                            critical,
+                        });
+                    }
+                }
+            }
+        }
+        trials
+    }
+    /// execute() runs the trials on all threads and writes one CSV row per trial
+    pub fn execute(&self) -> Result<(), String> {
+        if let Some(folder) = &self.images {
+            fs::create_dir_all(folder)
+                .map_err(|why| format!("Error creating {}: {}", folder, why))?;
+        }
+        let trials = self.trials();
+        let next = AtomicUsize::new(0);
+        let rows: Mutex<Vec<String>> = Mutex::new(vec![String::new(); trials.len()]);
+        thread::scope(|scope| {
+            for _ in 0..self.threads.min(trials.len()) {
+                scope.spawn(|| loop {
+                    let number = next.fetch_add(1, Ordering::Relaxed);
+                    if number >= trials.len() {
+                        break;
+                    }
+                    let row = self.row(number + 1, &trials[number]);
+                    eprintln!("run {} of {} finished", number + 1, trials.len());
+                    rows.lock().unwrap()[number] = row;
+                });
+            }
+        });
+        let mut csv = format!("{}\n", SWEEP_HEADER);
+        for row in rows.into_inner().unwrap() {
+            csv.push_str(&row);
+            csv.push('\n');
+        }
+        fs::write(&self.csv, csv).map_err(|why| format!("Error writing {}: {}", self.csv, why))?;
+        println!("{} runs written to {}", trials.len(), self.csv);
+        Ok(())
+    }
+    // row() runs one trial and describes it as a CSV row - a trial that can't run leaves a note instead of stopping the sweep
+    fn row(&self, number: usize, trial: &Trial) -> String {
+        let start = Instant::now();
+        let image = self
+            .images
+            .as_ref()
+            .map(|folder| format!("{}/sweep_{:04}.png", folder.trim_end_matches('/'), number));
+        let mut row = format!(
+            "{},{},{},{},",
+            number, trial.piles, trial.separation, trial.size
+        );
+        let mut run = match self.run(trial, &image) {
+            Ok(run) => run,
+            Err(why) => {
+                let critical = trial.critical.map_or(String::new(), |c| c.to_string());
+                row.push_str(&format!("{},,,,,,,,,,,,{}", critical, quote(&why)));
+                return row;
+            }
+        };
+        let saved = run.simulate().and_then(|_| run.save());
+        let model = &run.model;
+        let histogram = &model.histogram;
+        let lost = match model.rule.is_continuous() {
+            true => format!("{:.1}", model.lost_energy),
+            false => model.lost_grains.to_string(),
+        };
+        let _ = write!(
+            row,
+            "{},{},{},{},{},{:.3},{:.3},",
+            model.critical,
+            model.total_grains,
+            lost,
+            histogram.avalanches(),
+            histogram.largest(),
+            Histogram::mean(&histogram.topples),
+            Histogram::mean(&histogram.duration)
+        );
+        let size_fit = PowerLaw::fit(&histogram.topples, FIT_MIN);
+        let duration_fit = PowerLaw::fit(&histogram.duration, FIT_MIN);
+        for fit in [
+            size_fit.map(|fit| fit.exponent),
+            duration_fit.map(|fit| fit.exponent),
+            size_fit.map(|fit| fit.cutoff),
+        ] {
+            if let Some(fit) = fit {
+                let _ = write!(row, "{:.3}", fit);
+            }
+            row.push(',');
+        }
+        let note = match (&saved, model.saturated) {
+            (Err(why), _) => quote(why),
+            (Ok(()), true) => "saturated".to_string(),
+            (Ok(()), false) => String::new(),
+        };
+        let _ = write!(
+            row,
+            "{},{:.2},{}",
+            image.filter(|_| saved.is_ok()).unwrap_or_default(),
+            start.elapsed().as_secs_f32(),
+            note
+        );
+        row
+    }
+    // run() builds the Run for a trial from the shared options
+    fn run(&self, trial: &Trial, image: &Option<String>) -> Result<Run, String> {
+        let mut args = self.options.clone();
+        for (flag, option) in [("--width", trial.size), ("--height", trial.size)] {
+            args.push(flag.to_string());
+            args.push(option.to_string());
+        }
+        if let Some(critical) = trial.critical {
+            args.push("--critical".to_string());
+            args.push(critical.to_string());
+        }
+        for (x, y) in trial.drops()? {
+            args.push("--drops".to_string());
+            args.push(format!("{},{}", x, y));
+        }
+        if let Some(filename) = image {
+            args.push("--png".to_string());
+            args.push(filename.clone());
+        }
+        args.push("--quiet".to_string());
+        Run::parse(&args)
+    }
+}
+// range() parses a list of values and inclusive ranges such as "2,4,8" or "50..200:50"
+fn range(flag: &str, text: &str) -> Result<Vec<usize>, String> {
+    let mut values: Vec<usize> = Vec::new();
+    for item in text.split(',') {
+        let Some((first, rest)) = item.split_once("..") else {
+            values.push(value(flag, item)?);
+            continue;
+        };
+        let (last, step) = rest.split_once(':').unwrap_or((rest, "1"));
+        let (first, last, step): (usize, usize, usize) =
+            (value(flag, first)?, value(flag, last)?, value(flag, step)?);
+        if step == 0 || first > last {
+            return Err(format!("'{}' is not a valid range for {}", item, flag));
+        }
+        values.extend((first..=last).step_by(step));
+    }
+    Ok(values)
+}
+// quote() makes text safe to put in a CSV field
+fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('"', "\"\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn sweep_combinations() {
+        assert_eq!(
+            range("--size", "10,20..40:10").unwrap(),
+            vec![10, 20, 30, 40]
+        );
+        assert_eq!(range("--critical", "4..6").unwrap(), vec![4, 5, 6]);
+        assert!(range("--size", "40..20").is_err());
+        assert!(range("--size", "10..20:0").is_err());
+        // neighboring piles are the separation apart
+        let trial = Trial {
+            piles: 4,
+            separation: 20,
+            size: 100,
+            critical: None,
+        };
+        let drops = trial.drops().unwrap();
+        assert_eq!(drops[0], (64, 50));
+        let (dx, dy) = (
+            drops[1].0 as f64 - drops[0].0 as f64,
+            drops[1].1 as f64 - drops[0].1 as f64,
+        );
+        assert!(((dx * dx + dy * dy).sqrt() - 20.0).abs() < 1.5);
+        assert!(Trial { size: 10, ..trial }.drops().is_err());
+        // every combination is run, bad ones leave a note
+        let folder = std::env::temp_dir().join(format!("lakhesis_sweep_{}", std::process::id()));
+        let csv = folder.join("sweep.csv");
+        let sweep = Sweep::parse(&args(&format!(
+            "--piles 1..2 --separation 30 --size 20,40 --critical 4,8 --grains 500 --threads 3 --csv {} --images {} --boundary closed",
+            csv.display(),
+            folder.display()
+        )))
+        .unwrap();
+        assert_eq!(sweep.trials().len(), 8);
+        sweep.execute().unwrap();
+        let table = fs::read_to_string(&csv).unwrap();
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(lines.len(), 9);
+        assert_eq!(lines[0], SWEEP_HEADER);
+        assert!(lines[1].starts_with("1,1,30,20,4,500,0,"));
+        assert!(lines[5].starts_with("5,2,30,20,4,,") && lines[5].ends_with("lattice\""));
+        assert!(lines[1].contains("sweep_0001.png"));
+        assert!(folder.join("sweep_0008.png").exists());
+        assert!(Sweep::parse(&args("--drops 5,5")).is_err());
+        assert!(Sweep::parse(&args("--lattice pentagonal")).is_err());
+        // every run would write the same file
+        assert!(Sweep::parse(&args("--piles 1,2 --growth g.csv")).is_err());
+        assert!(Sweep::parse(&args("--piles 1,2 --growth-curve g.png")).is_err());
+        for seams in ["--seams s.csv", "--seam-lines s.csv", "--seam-mask s.png"] {
+            assert!(Sweep::parse(&args(&format!("--piles 1,2 {}", seams))).is_err());
+        }
+        assert!(Sweep::parse(&args("--piles 1,2 --patterns p.csv")).is_err());
+        fs::remove_dir_all(&folder).unwrap();
+    }
+}